@@ -19,6 +19,10 @@ const LOGO: &str = r#"
 ║                  ADVANCED AI INTERFACE SYSTEM v2.1                   ║
 ╚══════════════════════════════════════════════════════════════════════╝"#;
 
+fn clear_screen() -> io::Result<()> {
+    execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0))
+}
+
 fn type_effect(text: &str, delay: u64) {
     for c in text.chars() {
         print!("{}", c);
@@ -34,7 +38,7 @@ pub fn boot_sequence() {
     thread::sleep(Duration::from_secs(1));
 
     // Position cursor below the logo for messages
-    stdout.execute(MoveTo(0, 12)).unwrap();
+    execute!(stdout(), MoveTo(0, 12)).unwrap();
 
     let messages = [
         "INITIALIZING TERMINUS CORE SYSTEMS...",
@@ -54,22 +58,3 @@ pub fn boot_sequence() {
         thread::sleep(Duration::from_millis(500));
     }
 }
-
-pub fn show_menu() -> io::Result<String> {
-    println!("\nTERMINUS COMMAND INTERFACE");
-    println!("------------------------");
-    println!("1. Enter prompt");
-    println!("2. Select model");
-    println!("3. Set system message");
-    println!("4. Show system message");
-    println!("5. Add memory entry");
-    println!("6. Show memory");
-    println!("7. Toggle memory usage");
-    println!("8. Edit memory");
-    println!("9. Exit");
-    println!("\nEnter your choice (1-9): ");
-
-    let mut choice = String::new();
-    io::stdin().read_line(&mut choice)?;
-    Ok(choice.trim().to_string())
-}
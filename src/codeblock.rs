@@ -0,0 +1,185 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{stdin, stdout, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+
+use crate::markdown;
+
+/// A fenced code block from a response
+pub struct CodeBlock {
+    pub language: String,
+    pub code: String,
+}
+
+/// What a code block printed when it was run
+pub struct RunOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl CodeBlock {
+    /// The fenced code blocks in `text`, in order; an unclosed block runs to
+    /// the end of the text
+    pub fn extract(text: &str) -> Vec<CodeBlock> {
+        let mut blocks = Vec::new();
+        let mut open: Option<(String, CodeBlock)> = None;
+        for line in text.lines() {
+            let trimmed = line.trim_start();
+            match &mut open {
+                Some((fence, _)) if trimmed.trim_end() == fence => {
+                    blocks.extend(open.take().map(|(_, block)| block));
+                }
+                Some((_, block)) => {
+                    block.code.push_str(line);
+                    block.code.push('\n');
+                }
+                None => {
+                    if let Some(fence) = markdown::fence(trimmed) {
+                        let language = trimmed[fence.len()..].trim().to_lowercase();
+                        let code = String::new();
+                        open = Some((fence, CodeBlock { language, code }));
+                    }
+                }
+            }
+        }
+        blocks.extend(open.map(|(_, block)| block));
+        blocks
+    }
+
+    /// Block `number`, counting from 1 as in `print_list`
+    pub fn select(blocks: &[CodeBlock], number: usize) -> Result<&CodeBlock> {
+        match number.checked_sub(1).and_then(|i| blocks.get(i)) {
+            Some(block) => Ok(block),
+            None if blocks.is_empty() => bail!("The response has no code blocks"),
+            None => bail!(
+                "No code block {}; the response has {}",
+                number,
+                blocks.len()
+            ),
+        }
+    }
+
+    pub fn print_list(blocks: &[CodeBlock]) {
+        if blocks.is_empty() {
+            println!("The response has no code blocks");
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            let first_line = block.code.lines().next().unwrap_or_default().trim();
+            println!(
+                "  {}. {} ({} lines)  {}",
+                i + 1,
+                block.label(),
+                block.code.lines().count(),
+                first_line
+            );
+        }
+    }
+
+    pub fn label(&self) -> &str {
+        if self.language.is_empty() {
+            "text"
+        } else {
+            &self.language
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, &self.code).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    pub fn append(&self, path: &Path) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(self.code.as_bytes())?;
+        Ok(())
+    }
+
+    /// Runs a shell or Python block after showing it and asking first;
+    /// returns `None` if the user declines
+    pub fn run(&self) -> Result<Option<RunOutput>> {
+        let Some((program, flag)) = self.interpreter() else {
+            bail!(
+                "Only shell and Python blocks can be run, not {}",
+                self.label()
+            );
+        };
+        println!("{}", self.code.trim_end());
+        if !confirm(&format!("Run this {} block?", self.label()))? {
+            return Ok(None);
+        }
+
+        // The code is passed as an argument so stdin stays with the terminal
+        // for commands in the block that read it
+        let output = Command::new(program)
+            .arg(flag)
+            .arg(&self.code)
+            .stdin(Stdio::inherit())
+            .output()
+            .with_context(|| format!("Failed to start {}", program))?;
+        Ok(Some(RunOutput {
+            status: output.status,
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        }))
+    }
+
+    /// The interpreter for the block and the flag that makes it run code
+    /// given as its next argument
+    fn interpreter(&self) -> Option<(&'static str, &'static str)> {
+        match self.language.as_str() {
+            "sh" | "shell" | "console" => Some(("sh", "-c")),
+            "bash" => Some(("bash", "-c")),
+            "zsh" => Some(("zsh", "-c")),
+            "python" | "python3" | "py" => Some((python(), "-c")),
+            _ => None,
+        }
+    }
+}
+
+impl RunOutput {
+    pub fn print(&self) {
+        print!("{}", self.stdout);
+        eprint!("{}", self.stderr);
+        println!("[exited with {}]", self.status);
+    }
+
+    /// Offers to send the output to the model, returning the follow-up prompt
+    /// if the user accepts
+    pub fn follow_up(&self, number: usize) -> Result<Option<String>> {
+        Ok(confirm("Send the output to the model?")?.then(|| self.as_prompt(number)))
+    }
+
+    fn as_prompt(&self, number: usize) -> String {
+        let mut prompt = format!(
+            "I ran code block {} and it exited with {}.",
+            number, self.status
+        );
+        for (name, text) in [("stdout", &self.stdout), ("stderr", &self.stderr)] {
+            if !text.trim().is_empty() {
+                prompt.push_str(&format!("\n\n{}:\n```\n{}\n```", name, text.trim_end()));
+            }
+        }
+        prompt
+    }
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N]: ", question);
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+fn python() -> &'static str {
+    if cfg!(windows) {
+        "python"
+    } else {
+        "python3"
+    }
+}
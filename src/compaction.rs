@@ -0,0 +1,116 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::io::{stderr, stdin, Write};
+
+use crate::diff;
+use crate::llm::{self, OpenAIClient};
+use crate::memory::Memory;
+use crate::storage;
+
+const COMPACTION_PROMPT: &str = r#"You maintain a personal memory store made of numbered entries.
+Cluster entries that state the same fact, flag entries that contradict each other,
+and propose a condensed set of entries that keeps every distinct fact.
+Prefer the most recent entry (highest number) when resolving contradictions.
+Reply with JSON only, using this shape:
+{
+  "duplicates": [[1, 4]],
+  "contradictions": [{"entries": [2, 5], "explanation": "..."}],
+  "compacted": ["entry text", "..."]
+}"#;
+
+#[derive(Deserialize)]
+struct CompactionPlan {
+    #[serde(default)]
+    duplicates: Vec<Vec<usize>>,
+    #[serde(default)]
+    contradictions: Vec<Contradiction>,
+    compacted: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct Contradiction {
+    entries: Vec<usize>,
+    explanation: String,
+}
+
+/// Asks the model for a condensed memory set and saves it once the user approves the diff
+pub async fn compact(client: &OpenAIClient) -> Result<()> {
+    let entries = Memory::entries()?;
+    if entries.len() < 2 {
+        eprintln!("Memory has fewer than two entries, nothing to compact");
+        return Ok(());
+    }
+
+    let numbered = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| format!("{}. {}", i + 1, entry.content))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let response = client
+        .complete_with_system(&numbered, COMPACTION_PROMPT, None)
+        .await?;
+    let plan: CompactionPlan = serde_json::from_str(llm::strip_code_fence(&response.content))
+        .context("Model returned an invalid compaction plan")?;
+
+    if !plan.duplicates.is_empty() {
+        eprintln!("Duplicate clusters:");
+        for cluster in &plan.duplicates {
+            eprintln!("  {}", format_entries(cluster));
+        }
+    }
+    if !plan.contradictions.is_empty() {
+        eprintln!("Contradictions:");
+        for contradiction in &plan.contradictions {
+            eprintln!(
+                "  {}: {}",
+                format_entries(&contradiction.entries),
+                contradiction.explanation
+            );
+        }
+    }
+
+    let current = Memory::load()?;
+    let compacted = plan
+        .compacted
+        .iter()
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .collect::<Vec<_>>();
+    let proposed = compacted.join("\n\n");
+    if proposed == current.trim() {
+        eprintln!("Memory is already compact");
+        return Ok(());
+    }
+
+    eprintln!("\nProposed changes:");
+    eprint!("{}", diff::format_diff(&current, &proposed));
+
+    eprint!("\nApply compacted memory? [y/N]: ");
+    stderr().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    if answer.trim().eq_ignore_ascii_case("y") {
+        let _lock = storage::lock()?;
+        if Memory::load()? != current {
+            bail!("Memory changed while compacting; run the compaction again");
+        }
+        Memory::save(&proposed)?;
+        eprintln!(
+            "Memory compacted from {} to {} entries",
+            entries.len(),
+            compacted.len()
+        );
+    } else {
+        eprintln!("Memory left unchanged");
+    }
+    Ok(())
+}
+
+fn format_entries(entries: &[usize]) -> String {
+    entries
+        .iter()
+        .map(|i| format!("#{}", i))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
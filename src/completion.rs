@@ -0,0 +1,102 @@
+use anyhow::{Context, Result};
+use clap::Command;
+use clap_complete::engine::CompletionCandidate;
+use clap_complete::env::Shells;
+use clap_complete::Shell;
+use std::env;
+use std::fs;
+use std::io::{stdout, Write};
+use std::path::{Path, PathBuf};
+
+use crate::paths;
+use crate::persona::Persona;
+use crate::prompttemplate::PromptTemplate;
+use crate::session::Session;
+use crate::AVAILABLE_MODELS;
+
+/// Environment variable the shell scripts set when asking for completions
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Writes the script that hooks `terminus` completions into `shell`
+pub fn write_registration(shell: Shell) -> Result<()> {
+    let name = shell.to_string();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&name)
+        .with_context(|| format!("Completions are not supported for {}", name))?;
+    let mut out = stdout();
+    completer.write_registration(COMPLETE_VAR, "terminus", "terminus", "terminus", &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Writes the main man page to stdout, or pages for every subcommand into `dir`
+pub fn write_man_pages(command: Command, dir: Option<&Path>) -> Result<()> {
+    match dir {
+        Some(dir) => {
+            fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(command, dir)?;
+        }
+        None => clap_mangen::Man::new(command).render(&mut stdout())?,
+    }
+    Ok(())
+}
+
+/// Points the store at the `--home` or `--portable` given on the command line
+/// being completed, so candidates come from the store that command will use.
+/// `TERMINUS_HOME` needs nothing extra, since `paths::home` reads it anyway
+pub fn use_completed_home() {
+    if env::var_os(COMPLETE_VAR).is_none() {
+        return;
+    }
+    // The shell passes the words being completed after `--`
+    let words = env::args()
+        .skip_while(|arg| arg != "--")
+        .collect::<Vec<_>>();
+    for (i, word) in words.iter().enumerate() {
+        let home = match word.strip_prefix("--home=") {
+            Some(home) => Some(PathBuf::from(home)),
+            None if word == "--home" => words.get(i + 1).map(PathBuf::from),
+            None if word == "--portable" => paths::portable_dir().ok(),
+            None => None,
+        };
+        if let Some(home) = home {
+            let _ = paths::set_home(home);
+            return;
+        }
+    }
+}
+
+// Candidates come from file names in the local stores, so completing never
+// decrypts anything or asks for a passphrase
+
+pub fn persona_names() -> Vec<CompletionCandidate> {
+    Persona::names()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+pub fn template_names() -> Vec<CompletionCandidate> {
+    PromptTemplate::names()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+pub fn session_ids() -> Vec<CompletionCandidate> {
+    Session::list()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+pub fn model_names() -> Vec<CompletionCandidate> {
+    AVAILABLE_MODELS
+        .iter()
+        .map(|model| CompletionCandidate::new(*model))
+        .collect()
+}
@@ -0,0 +1,436 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{stdin, stdout, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::editor;
+use crate::persona::Persona;
+use crate::settings::Settings;
+use crate::storage;
+
+const PROJECT_FILE: &str = ".terminus.toml";
+
+// Values given as command line flags, set once at startup
+static CLI_OVERRIDES: OnceLock<Map<String, Value>> = OnceLock::new();
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Bool,
+    String,
+    OptionalString,
+    OptionalFloat { min: f64, max: f64 },
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Bool => write!(f, "true or false"),
+            Kind::String => write!(f, "text"),
+            Kind::OptionalString => write!(f, "text, or empty to clear"),
+            Kind::OptionalFloat { min, max } => {
+                write!(f, "a number from {} to {}, or empty to clear", min, max)
+            }
+        }
+    }
+}
+
+pub struct Key {
+    pub name: &'static str,
+    pub kind: Kind,
+    pub description: &'static str,
+    /// Only the global settings file and `TERMINUS_*` variables you export
+    /// yourself may set it, because it runs commands or writes outside the
+    /// project. Project files and `.env` files come with whatever repository
+    /// you cloned, so the former reject it and the latter are only read for
+    /// the API key (see `Credentials::load_dotenv`)
+    pub global_only: bool,
+}
+
+/// Settings that can be supplied by any configuration layer
+pub const KEYS: &[Key] = &[
+    Key {
+        name: "model",
+        kind: Kind::String,
+        description: "Model used when no persona or template picks one",
+        global_only: false,
+    },
+    Key {
+        name: "use_memory",
+        kind: Kind::Bool,
+        description: "Send memory entries as context with each prompt",
+        global_only: false,
+    },
+    Key {
+        name: "memory_sync",
+        kind: Kind::Bool,
+        description: "Commit memory changes to a local git repository",
+        global_only: true,
+    },
+    Key {
+        name: "memory_sync_remote",
+        kind: Kind::OptionalString,
+        description: "Repository path that `memory sync pull` merges from",
+        global_only: true,
+    },
+    Key {
+        name: "persona",
+        kind: Kind::OptionalString,
+        description: "Active persona",
+        global_only: false,
+    },
+    Key {
+        name: "temperature",
+        kind: Kind::OptionalFloat { min: 0.0, max: 2.0 },
+        description: "Default sampling temperature",
+        global_only: false,
+    },
+    Key {
+        name: "top_p",
+        kind: Kind::OptionalFloat { min: 0.0, max: 1.0 },
+        description: "Default nucleus sampling probability",
+        global_only: false,
+    },
+    Key {
+        name: "api_key_command",
+        kind: Kind::OptionalString,
+        description: "Shell command that prints the API key, e.g. `pass show openai`",
+        global_only: true,
+    },
+    Key {
+        name: "shell_history",
+        kind: Kind::Bool,
+        description: "Add commands run by `terminus cmd` to your shell history",
+        global_only: false,
+    },
+];
+
+/// Where a configuration value came from, lowest precedence first
+#[derive(Clone, Debug)]
+pub enum Source {
+    Default,
+    Global(PathBuf),
+    Project(PathBuf),
+    Environment(String),
+    Cli,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "built-in default"),
+            Source::Global(path) => write!(f, "global config {}", path.display()),
+            Source::Project(path) => write!(f, "project config {}", path.display()),
+            Source::Environment(var) => write!(f, "environment variable {}", var),
+            Source::Cli => write!(f, "command line flag"),
+        }
+    }
+}
+
+pub struct Resolved {
+    pub settings: Settings,
+    /// Every layer that set each key, lowest precedence first
+    pub layers: BTreeMap<String, Vec<(Source, Value)>>,
+}
+
+pub fn set_cli_overrides(overrides: Map<String, Value>) {
+    let _ = CLI_OVERRIDES.set(overrides);
+}
+
+/// Merges defaults, global settings, the project file, `TERMINUS_*` variables and flags
+pub fn resolve() -> Result<Resolved> {
+    let mut layers: BTreeMap<String, Vec<(Source, Value)>> = BTreeMap::new();
+    let mut values = to_map(&Settings::default())?;
+    for key in KEYS {
+        layers
+            .entry(key.name.to_string())
+            .or_default()
+            .push((Source::Default, values[key.name].clone()));
+    }
+
+    let global_path = Settings::global_path()?;
+    if global_path.exists() {
+        values = to_map(&Settings::load_global()?)?;
+        for key in KEYS {
+            layers.entry(key.name.to_string()).or_default().push((
+                Source::Global(global_path.clone()),
+                values[key.name].clone(),
+            ));
+        }
+    }
+
+    if let Some(path) = find_project_file()? {
+        let contents = fs::read_to_string(&path)?;
+        let project = parse_project(&contents).with_context(|| format!("In {}", path.display()))?;
+        for (key, value) in project {
+            apply(
+                &mut values,
+                &mut layers,
+                &key,
+                value,
+                Source::Project(path.clone()),
+            );
+        }
+    }
+
+    for key in KEYS {
+        let var = format!("TERMINUS_{}", key.name.to_uppercase());
+        if let Ok(raw) = env::var(&var) {
+            let value = parse_value(key, &raw).with_context(|| format!("Invalid {}", var))?;
+            apply(
+                &mut values,
+                &mut layers,
+                key.name,
+                value,
+                Source::Environment(var),
+            );
+        }
+    }
+
+    if let Some(overrides) = CLI_OVERRIDES.get() {
+        for (key, value) in overrides {
+            apply(&mut values, &mut layers, key, value.clone(), Source::Cli);
+        }
+    }
+
+    let settings = serde_json::from_value(Value::Object(values))?;
+    Ok(Resolved { settings, layers })
+}
+
+/// Parses and checks the values in a project file
+fn parse_project(contents: &str) -> Result<Vec<(String, Value)>> {
+    let table: toml::Table = toml::from_str(contents).context("Not valid TOML")?;
+    let mut values = Vec::new();
+    for (key, value) in table {
+        let value = serde_json::to_value(value)?;
+        let definition = find_key(&key)?;
+        if definition.global_only {
+            bail!(
+                "'{}' can't be set in a project file; set it with `terminus config set {}` \
+                 or the TERMINUS_{} environment variable",
+                key,
+                key,
+                key.to_uppercase()
+            );
+        }
+        check_value(definition, &value).context("Invalid value")?;
+        values.push((key, value));
+    }
+    Ok(values)
+}
+
+/// Looks up a setting, suggesting the closest name for typos
+pub fn find_key(name: &str) -> Result<&'static Key> {
+    if let Some(key) = KEYS.iter().find(|key| key.name == name) {
+        return Ok(key);
+    }
+    let names = KEYS.iter().map(|key| key.name).collect::<Vec<_>>();
+    match names
+        .iter()
+        .min_by_key(|candidate| edit_distance(name, candidate))
+        .filter(|candidate| edit_distance(name, candidate) <= 3)
+    {
+        Some(suggestion) => bail!(
+            "Unknown setting '{}'. Did you mean '{}'? Valid settings: {}",
+            name,
+            suggestion,
+            names.join(", ")
+        ),
+        None => bail!(
+            "Unknown setting '{}'. Valid settings: {}",
+            name,
+            names.join(", ")
+        ),
+    }
+}
+
+/// Parses a string given on the command line or in the environment
+pub fn parse_value(key: &Key, raw: &str) -> Result<Value> {
+    let value = match key.kind {
+        Kind::Bool => match raw.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Value::Bool(true),
+            "false" | "0" | "no" | "off" => Value::Bool(false),
+            _ => bail!("'{}' expects {}, got '{}'", key.name, key.kind, raw),
+        },
+        Kind::String => Value::String(raw.to_string()),
+        Kind::OptionalString | Kind::OptionalFloat { .. } if raw.is_empty() => Value::Null,
+        Kind::OptionalString => Value::String(raw.to_string()),
+        Kind::OptionalFloat { .. } => match raw.trim().parse::<f64>() {
+            Ok(number) => number.into(),
+            Err(_) => bail!("'{}' expects {}, got '{}'", key.name, key.kind, raw),
+        },
+    };
+    check_value(key, &value)?;
+    Ok(value)
+}
+
+/// Checks a value's type and range
+pub fn check_value(key: &Key, value: &Value) -> Result<()> {
+    let valid = match key.kind {
+        Kind::Bool => value.is_boolean(),
+        Kind::String => value.as_str().is_some_and(|s| !s.is_empty()),
+        Kind::OptionalString => value.is_string() || value.is_null(),
+        Kind::OptionalFloat { min, max } => {
+            value.is_null() || value.as_f64().is_some_and(|n| n >= min && n <= max)
+        }
+    };
+    if !valid {
+        bail!("'{}' expects {}, got {}", key.name, key.kind, value);
+    }
+    Ok(())
+}
+
+/// Validates and stores a value in the global settings file
+pub fn set(name: &str, raw: &str) -> Result<Value> {
+    let key = find_key(name)?;
+    let value = parse_value(key, raw)?;
+    if let (Some(persona), "persona") = (value.as_str(), key.name) {
+        if !Persona::exists(persona)? {
+            bail!("Persona not found: {}", persona);
+        }
+    }
+    update_global(key, value.clone())?;
+    Ok(value)
+}
+
+/// Resets a key in the global settings file to its default
+pub fn unset(name: &str) -> Result<Value> {
+    let key = find_key(name)?;
+    let default = to_map(&Settings::default())?[key.name].clone();
+    update_global(key, default.clone())?;
+    Ok(default)
+}
+
+/// Opens the global settings file in an editor, re-opening it until it's valid
+pub fn edit() -> Result<()> {
+    let path = Settings::global_path()?;
+    if !path.exists() {
+        Settings::default().save()?;
+    }
+    let original = fs::read_to_string(&path)?;
+    loop {
+        editor::edit_file(&path)?;
+        let Err(error) = check_file(&path) else {
+            return Ok(());
+        };
+        eprintln!("Invalid settings: {:#}", error);
+        print!("Re-open the editor? [Y/n]: ");
+        stdout().flush()?;
+        let mut answer = String::new();
+        stdin().read_line(&mut answer)?;
+        if answer.trim().eq_ignore_ascii_case("n") {
+            storage::write_atomic(&path, original.as_bytes())?;
+            bail!("Settings left unchanged");
+        }
+    }
+}
+
+/// Formats a value for display, without quotes around strings
+pub fn display(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => "(unset)".to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn check_file(path: &Path) -> Result<()> {
+    let value: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let (settings, _) = Settings::migrate(value)?;
+    let values = to_map(&settings)?;
+    for key in KEYS {
+        check_value(key, &values[key.name])?;
+    }
+    Ok(())
+}
+
+fn update_global(key: &Key, value: Value) -> Result<()> {
+    let _lock = storage::lock()?;
+    let mut values = to_map(&Settings::load_global()?)?;
+    values.insert(key.name.to_string(), value);
+    let settings: Settings = serde_json::from_value(Value::Object(values))?;
+    settings.save()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn apply(
+    values: &mut Map<String, Value>,
+    layers: &mut BTreeMap<String, Vec<(Source, Value)>>,
+    key: &str,
+    value: Value,
+    source: Source,
+) {
+    values.insert(key.to_string(), value.clone());
+    layers
+        .entry(key.to_string())
+        .or_default()
+        .push((source, value));
+}
+
+fn to_map(settings: &Settings) -> Result<Map<String, Value>> {
+    match serde_json::to_value(settings)? {
+        Value::Object(map) => Ok(map),
+        _ => unreachable!("settings always serialize to an object"),
+    }
+}
+
+/// Looks for `.terminus.toml` in the current directory and its parents
+fn find_project_file() -> Result<Option<PathBuf>> {
+    let mut dir = env::current_dir()?;
+    loop {
+        let candidate = dir.join(PROJECT_FILE);
+        if candidate.is_file() {
+            return Ok(Some(candidate));
+        }
+        if !dir.pop() {
+            return Ok(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_project_values() {
+        let values = parse_project("model = \"gpt-4o\"\ntemperature = 0.2\n").unwrap();
+        assert_eq!(values[0], ("model".to_string(), Value::from("gpt-4o")));
+        assert_eq!(values[1], ("temperature".to_string(), Value::from(0.2)));
+    }
+
+    #[test]
+    fn rejects_commands_in_project_files() {
+        let error = parse_project("api_key_command = \"touch /tmp/pwned\"\n").unwrap_err();
+        assert!(error.to_string().contains("can't be set in a project file"));
+    }
+
+    #[test]
+    fn rejects_sync_settings_in_project_files() {
+        assert!(parse_project("memory_sync = true\n").is_err());
+        assert!(parse_project("memory_sync_remote = \"/tmp/elsewhere\"\n").is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_project_values() {
+        assert!(parse_project("temperature = 5.0\n").is_err());
+        assert!(parse_project("modle = \"gpt-4o\"\n").is_err());
+    }
+}
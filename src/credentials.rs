@@ -0,0 +1,207 @@
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::crypto::Vault;
+use crate::paths;
+use crate::settings::Settings;
+use crate::storage;
+
+const API_KEY_VAR: &str = "OPENAI_API_KEY";
+
+// Resolved key, cached so a helper command only runs once per process
+static API_KEY: Mutex<Option<String>> = parking_lot::const_mutex(None);
+
+#[derive(Serialize, Deserialize, Default)]
+struct CredentialsFile {
+    openai_api_key: Option<String>,
+}
+
+/// Where an API key can come from, in the order they are tried
+pub enum Provider {
+    Environment,
+    DotEnv(PathBuf),
+    Helper(String),
+    File(PathBuf),
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Provider::Environment => write!(f, "environment variable {}", API_KEY_VAR),
+            Provider::DotEnv(path) => write!(f, "{}", path.display()),
+            Provider::Helper(command) => write!(f, "helper command `{}`", command),
+            Provider::File(path) => write!(f, "encrypted credentials file {}", path.display()),
+        }
+    }
+}
+
+pub struct Credentials;
+
+impl Credentials {
+    /// The OpenAI API key from the first provider that has one
+    pub fn api_key() -> Result<String> {
+        let mut cached = API_KEY.lock();
+        if let Some(key) = cached.as_ref() {
+            return Ok(key.clone());
+        }
+        let Some(provider) = Self::provider()? else {
+            bail!(
+                "No OpenAI API key found. Set {}, add it to {}, configure a helper with \
+                 `terminus config set api_key_command \"pass show openai\"`, or store it with \
+                 `terminus credentials set`",
+                API_KEY_VAR,
+                Self::dotenv_path()?.display()
+            );
+        };
+        let key =
+            Self::read(&provider).with_context(|| format!("Reading API key from {}", provider))?;
+        if key.is_empty() {
+            bail!("{} returned an empty API key", provider);
+        }
+        *cached = Some(key.clone());
+        Ok(key)
+    }
+
+    /// Loads the API key from a `.env` in the working directory or its parents
+    /// unless it's already set. Nothing else is taken from that file, since it
+    /// comes with whatever repository you're in and could otherwise set
+    /// `TERMINUS_*` variables such as `TERMINUS_API_KEY_COMMAND`
+    pub fn load_dotenv() {
+        if env::var_os(API_KEY_VAR).is_some() {
+            return;
+        }
+        let Ok(mut dir) = env::current_dir() else {
+            return;
+        };
+        loop {
+            let path = dir.join(".env");
+            if path.is_file() {
+                if let Ok(Some(key)) = Self::dotenv_key(&path) {
+                    env::set_var(API_KEY_VAR, key);
+                }
+                return;
+            }
+            if !dir.pop() {
+                return;
+            }
+        }
+    }
+
+    /// The first provider that is configured, without reading the key itself
+    pub fn provider() -> Result<Option<Provider>> {
+        if env::var(API_KEY_VAR).is_ok_and(|key| !key.is_empty()) {
+            return Ok(Some(Provider::Environment));
+        }
+        let dotenv = Self::dotenv_path()?;
+        if Self::dotenv_key(&dotenv)?.is_some() {
+            return Ok(Some(Provider::DotEnv(dotenv)));
+        }
+        if let Some(command) = Settings::load()?.api_key_command {
+            return Ok(Some(Provider::Helper(command)));
+        }
+        let file = Self::file_path()?;
+        if file.exists() {
+            return Ok(Some(Provider::File(file)));
+        }
+        Ok(None)
+    }
+
+    /// Stores the key in the credentials file, which is always encrypted
+    pub fn store(api_key: &str) -> Result<()> {
+        if !Vault::is_enabled()? {
+            bail!("Credentials are only stored encrypted; run `terminus store encrypt` first");
+        }
+        let credentials = CredentialsFile {
+            openai_api_key: Some(api_key.to_string()),
+        };
+        storage::write(
+            &Self::file_path()?,
+            &serde_json::to_string_pretty(&credentials)?,
+        )
+    }
+
+    pub fn clear() -> Result<bool> {
+        let path = Self::file_path()?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        fs::remove_file(path)?;
+        Ok(true)
+    }
+
+    pub fn is_stored() -> Result<bool> {
+        Ok(Self::file_path()?.exists())
+    }
+
+    fn read(provider: &Provider) -> Result<String> {
+        match provider {
+            Provider::Environment => Ok(env::var(API_KEY_VAR)?.trim().to_string()),
+            Provider::DotEnv(path) => Ok(Self::dotenv_key(path)?.unwrap_or_default()),
+            Provider::Helper(command) => run_helper(command),
+            Provider::File(path) => {
+                let contents = storage::read(path)?.unwrap_or_default();
+                let credentials: CredentialsFile = serde_json::from_str(&contents)?;
+                Ok(credentials.openai_api_key.unwrap_or_default())
+            }
+        }
+    }
+
+    fn dotenv_key(path: &Path) -> Result<Option<String>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = fs::read_to_string(path)?;
+        let value = contents.lines().find_map(|line| {
+            let line = line.trim();
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            (name.trim() == API_KEY_VAR).then(|| value.trim().trim_matches(['"', '\'']).to_string())
+        });
+        Ok(value.filter(|value| !value.is_empty()))
+    }
+
+    /// `.env` in the Terminus home; one in the working directory only supplies
+    /// the key through `load_dotenv`
+    fn dotenv_path() -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push(".env");
+        Ok(path)
+    }
+
+    fn file_path() -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push("credentials.json");
+        Ok(path)
+    }
+}
+
+/// Runs a helper such as `pass show openai` and uses the first line of its output
+fn run_helper(command: &str) -> Result<String> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    // Inherit stdin and stderr so helpers can ask for a passphrase
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .with_context(|| format!("Failed to run `{}`", command))?;
+    if !output.status.success() {
+        bail!("`{}` exited with {}", command, output.status);
+    }
+    let stdout = String::from_utf8(output.stdout).context("Helper output is not valid UTF-8")?;
+    Ok(stdout.lines().next().unwrap_or_default().trim().to_string())
+}
@@ -0,0 +1,194 @@
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use parking_lot::Mutex;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::paths;
+use crate::storage;
+
+/// Prefix written in front of every encrypted file
+const MAGIC: &[u8] = b"TERMINUS-ENC1\n";
+const NONCE_LEN: usize = 24;
+const SALT_LEN: usize = 16;
+const CHECK_PLAINTEXT: &[u8] = b"terminus-vault-check";
+
+// Derived key, cached so the passphrase is only asked for once per process
+static KEY: Mutex<Option<Key>> = parking_lot::const_mutex(None);
+
+#[derive(Serialize, Deserialize)]
+struct VaultHeader {
+    kdf: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: String,
+    check: String,
+}
+
+pub struct Vault;
+
+impl Vault {
+    pub fn is_enabled() -> Result<bool> {
+        Ok(Self::vault_path()?.exists())
+    }
+
+    pub fn is_encrypted(data: &[u8]) -> bool {
+        data.starts_with(MAGIC)
+    }
+
+    /// Creates a new vault protected by `passphrase` and unlocks it
+    pub fn create(passphrase: &str) -> Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+
+        // OWASP recommended Argon2id parameters
+        let params = Params::new(19 * 1024, 2, 1, Some(32))
+            .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+        let key = Self::derive_key(passphrase, &salt, &params)?;
+        let check = Self::seal(&key, CHECK_PLAINTEXT)?;
+
+        let header = VaultHeader {
+            kdf: "argon2id".to_string(),
+            m_cost: params.m_cost(),
+            t_cost: params.t_cost(),
+            p_cost: params.p_cost(),
+            salt: to_hex(&salt),
+            check: to_hex(&check),
+        };
+
+        storage::write_atomic(
+            &Self::vault_path()?,
+            serde_json::to_string_pretty(&header)?.as_bytes(),
+        )?;
+        *KEY.lock() = Some(key);
+        Ok(())
+    }
+
+    pub fn remove() -> Result<()> {
+        let path = Self::vault_path()?;
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        *KEY.lock() = None;
+        Ok(())
+    }
+
+    pub fn encrypt(plaintext: &[u8]) -> Result<Vec<u8>> {
+        let key = Self::unlock()?;
+        Self::seal(&key, plaintext)
+    }
+
+    pub fn decrypt(data: &[u8]) -> Result<Vec<u8>> {
+        let key = Self::unlock()?;
+        Self::open(&key, data)
+    }
+
+    /// Asks for a new passphrase twice and returns it once both entries match,
+    /// unless one is already supplied through `TERMINUS_PASSPHRASE`
+    pub fn prompt_new_passphrase() -> Result<String> {
+        if let Ok(passphrase) = env::var("TERMINUS_PASSPHRASE") {
+            if passphrase.is_empty() {
+                bail!("TERMINUS_PASSPHRASE is set but empty; the passphrase must not be empty");
+            }
+            return Ok(passphrase);
+        }
+        let passphrase = rpassword::prompt_password("New passphrase: ")?;
+        if passphrase.is_empty() {
+            bail!("Passphrase must not be empty");
+        }
+        let confirm = rpassword::prompt_password("Confirm passphrase: ")?;
+        if passphrase != confirm {
+            bail!("Passphrases do not match");
+        }
+        Ok(passphrase)
+    }
+
+    fn unlock() -> Result<Key> {
+        let mut cached = KEY.lock();
+        if let Some(key) = cached.as_ref() {
+            return Ok(*key);
+        }
+
+        let path = Self::vault_path()?;
+        let contents = fs::read_to_string(&path)
+            .context("Store is encrypted but the vault header is missing")?;
+        let header: VaultHeader = serde_json::from_str(&contents)?;
+        if header.kdf != "argon2id" {
+            bail!("Unsupported key derivation function: {}", header.kdf);
+        }
+        let params = Params::new(header.m_cost, header.t_cost, header.p_cost, Some(32))
+            .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+        let salt = from_hex(&header.salt)?;
+        let check = from_hex(&header.check)?;
+
+        let passphrase = match env::var("TERMINUS_PASSPHRASE") {
+            Ok(passphrase) => passphrase,
+            Err(_) => rpassword::prompt_password("Passphrase: ")?,
+        };
+        let key = Self::derive_key(&passphrase, &salt, &params)?;
+        Self::open(&key, &check).map_err(|_| anyhow!("Incorrect passphrase"))?;
+
+        *cached = Some(key);
+        Ok(key)
+    }
+
+    fn derive_key(passphrase: &str, salt: &[u8], params: &Params) -> Result<Key> {
+        let mut key = Key::default();
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params.clone())
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        Ok(key)
+    }
+
+    fn seal(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = XChaCha20Poly1305::new(key)
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let mut data = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    fn open(key: &Key, data: &[u8]) -> Result<Vec<u8>> {
+        if !Self::is_encrypted(data) || data.len() < MAGIC.len() + NONCE_LEN {
+            bail!("Data is not a valid encrypted file");
+        }
+        let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        XChaCha20Poly1305::new(key)
+            .decrypt(XNonce::from_slice(nonce), ciphertext)
+            .map_err(|_| anyhow!("Decryption failed: wrong passphrase or corrupted file"))
+    }
+
+    fn vault_path() -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push("vault.json");
+        Ok(path)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+        bail!("Invalid hex string in vault header");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16).context("Invalid hex string in vault header")
+        })
+        .collect()
+}
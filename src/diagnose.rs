@@ -0,0 +1,300 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use std::env;
+use std::fs;
+use std::io::{self, stderr, stdin, stdout, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+use crate::markdown::Renderer;
+use crate::output::{self, OutputFormat};
+use crate::persona::Persona;
+use crate::session::Session;
+use crate::settings::Settings;
+
+// How much output is sent: the start, where the first error usually is, and
+// the end, where the summary is
+const HEAD_LINES: usize = 40;
+const TAIL_LINES: usize = 160;
+const LINE_LIMIT: usize = 500;
+
+// Files mentioned in the output that are sent along, with this many lines
+// either side of the mentioned line
+const MAX_FILES: usize = 3;
+const CONTEXT_LINES: usize = 20;
+
+struct Outcome {
+    status: ExitStatus,
+    output: String,
+}
+
+/// Runs `command`, and while it fails, asks the model why and offers to run it
+/// again once the fix is in. Returns the exit status of the last run
+pub async fn run(
+    command: &[String],
+    settings: &Settings,
+    persona: Option<&Persona>,
+    format: OutputFormat,
+    raw: bool,
+) -> Result<ExitStatus> {
+    let display = display_command(command);
+    // Later diagnoses continue the conversation, so the model sees what it
+    // already suggested
+    let mut session = Session::new();
+    loop {
+        let outcome = execute(command, format)?;
+        if outcome.status.success() {
+            return Ok(outcome.status);
+        }
+        eprintln!(
+            "\n`{}` failed with {}; asking for a diagnosis",
+            display, outcome.status
+        );
+
+        let prompt = diagnosis_prompt(&display, &outcome)?;
+        let mut renderer = output::renders(format, raw).then(Renderer::new);
+        let exchange = crate::send_prompt(
+            &prompt,
+            settings,
+            persona,
+            None,
+            &mut session,
+            renderer.as_mut(),
+        )
+        .await?;
+        output::print_exchange(&exchange, &session.id, format, false, renderer.is_some())?;
+
+        if !stdin().is_terminal() {
+            return Ok(outcome.status);
+        }
+        eprint!("\nApply the fix, then re-run `{}`? [y/N]: ", display);
+        stderr().flush()?;
+        let mut answer = String::new();
+        stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            return Ok(outcome.status);
+        }
+    }
+}
+
+/// Runs the command with its output shown as usual, keeping a copy of both
+/// streams in the order they arrive. With structured output the command's
+/// stdout goes to stderr, so stdout only carries terminus's own records
+fn execute(command: &[String], format: OutputFormat) -> Result<Outcome> {
+    let (program, args) = command.split_first().context("No command to run")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run `{}`", program))?;
+
+    let captured = Arc::new(Mutex::new(Vec::new()));
+    let stdout_pipe = child.stdout.take().context("Failed to capture stdout")?;
+    let stderr_pipe = child.stderr.take().context("Failed to capture stderr")?;
+    let shown: Box<dyn Write + Send> = if format == OutputFormat::Text {
+        Box::new(stdout())
+    } else {
+        Box::new(stderr())
+    };
+    let readers = [
+        tee(stdout_pipe, shown, Arc::clone(&captured)),
+        tee(stderr_pipe, stderr(), Arc::clone(&captured)),
+    ];
+    let status = child.wait()?;
+    for reader in readers {
+        let _ = reader.join();
+    }
+
+    let output = String::from_utf8_lossy(&captured.lock()).into_owned();
+    Ok(Outcome { status, output })
+}
+
+fn tee(
+    mut from: impl Read + Send + 'static,
+    mut to: impl Write + Send + 'static,
+    captured: Arc<Mutex<Vec<u8>>>,
+) -> thread::JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let read = from.read(&mut buffer)?;
+            if read == 0 {
+                return Ok(());
+            }
+            to.write_all(&buffer[..read])?;
+            to.flush()?;
+            captured.lock().extend_from_slice(&buffer[..read]);
+        }
+    })
+}
+
+fn diagnosis_prompt(display: &str, outcome: &Outcome) -> Result<String> {
+    let cwd = env::current_dir()?.canonicalize()?;
+    let mut prompt = format!(
+        "The command `{}` failed with {} in {}.\n\nOutput:\n```\n{}\n```\n",
+        display,
+        outcome.status,
+        cwd.display(),
+        truncate(&outcome.output)
+    );
+    for (path, line) in mentioned_files(&outcome.output, &cwd) {
+        // Skip files that aren't text
+        let Ok(contents) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let first = line.saturating_sub(CONTEXT_LINES + 1);
+        let snippet = contents
+            .lines()
+            .enumerate()
+            .skip(first)
+            .take(CONTEXT_LINES * 2 + 1)
+            .map(|(i, text)| format!("{:>5} | {}", i + 1, text))
+            .collect::<Vec<_>>()
+            .join("\n");
+        prompt.push_str(&format!(
+            "\n{} (around line {}):\n```\n{}\n```\n",
+            path.strip_prefix(&cwd).unwrap_or(&path).display(),
+            line,
+            snippet
+        ));
+    }
+    prompt.push_str("\nExplain what went wrong and propose a fix.");
+    Ok(prompt)
+}
+
+/// Keeps the start and end of long output and shortens very long lines
+fn truncate(output: &str) -> String {
+    let lines = output
+        .trim_end()
+        .lines()
+        .map(|line| match line.char_indices().nth(LINE_LIMIT) {
+            Some((end, _)) => format!("{}...", &line[..end]),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if lines.len() <= HEAD_LINES + TAIL_LINES {
+        return lines.join("\n");
+    }
+    let omitted = lines.len() - HEAD_LINES - TAIL_LINES;
+    format!(
+        "{}\n... ({} lines omitted) ...\n{}",
+        lines[..HEAD_LINES].join("\n"),
+        omitted,
+        lines[lines.len() - TAIL_LINES..].join("\n")
+    )
+}
+
+/// Files under `cwd` that the output points at, such as `src/main.rs:12:5`
+/// or `File "app.py", line 12`, with the first line mentioned for each
+fn mentioned_files(output: &str, cwd: &Path) -> Vec<(PathBuf, usize)> {
+    let words = output
+        .split(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '(' | ')' | ','))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>();
+    let mut files: Vec<(PathBuf, usize)> = Vec::new();
+    for (i, word) in words.iter().enumerate() {
+        let mut parts = word.split(':');
+        let path = parts.next().unwrap_or_default();
+        let line = match parts.next().and_then(|line| line.parse().ok()) {
+            Some(line) => line,
+            None if words.get(i + 1) == Some(&"line") => {
+                match words.get(i + 2).and_then(|line| line.parse().ok()) {
+                    Some(line) => line,
+                    None => continue,
+                }
+            }
+            None => continue,
+        };
+        let Ok(path) = cwd.join(path).canonicalize() else {
+            continue;
+        };
+        if path.is_file() && path.starts_with(cwd) && !files.iter().any(|(known, _)| *known == path)
+        {
+            files.push((path, line));
+            if files.len() == MAX_FILES {
+                break;
+            }
+        }
+    }
+    files
+}
+
+fn display_command(command: &[String]) -> String {
+    command
+        .iter()
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_short_output() {
+        assert_eq!(truncate("one\ntwo\n"), "one\ntwo");
+    }
+
+    #[test]
+    fn shortens_long_lines_on_a_character_boundary() {
+        let line = "é".repeat(LINE_LIMIT + 5);
+        assert_eq!(truncate(&line), format!("{}...", "é".repeat(LINE_LIMIT)));
+    }
+
+    #[test]
+    fn keeps_the_start_and_end_of_long_output() {
+        let output = (1..=300)
+            .map(|i| format!("line {}", i))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let truncated = truncate(&output);
+        let lines = truncated.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), HEAD_LINES + 1 + TAIL_LINES);
+        assert_eq!(lines[HEAD_LINES - 1], "line 40");
+        assert_eq!(lines[HEAD_LINES], "... (100 lines omitted) ...");
+        assert_eq!(lines[HEAD_LINES + 1], "line 141");
+        assert_eq!(lines.last(), Some(&"line 300"));
+    }
+
+    #[test]
+    fn finds_files_mentioned_with_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().canonicalize().unwrap();
+        fs::create_dir(cwd.join("src")).unwrap();
+        fs::write(cwd.join("src/main.rs"), "fn main() {}\n").unwrap();
+        fs::write(cwd.join("app.py"), "print()\n").unwrap();
+
+        let output = "error: src/main.rs:12:5: expected `;`\n\
+                      warning at src/main.rs:30\n\
+                      File \"app.py\", line 7, in <module>\n\
+                      missing.rs:3 /etc/hostname:1 ../outside.rs:2";
+        assert_eq!(
+            mentioned_files(output, &cwd),
+            [(cwd.join("src/main.rs"), 12), (cwd.join("app.py"), 7)]
+        );
+    }
+
+    #[test]
+    fn ignores_paths_without_a_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let cwd = dir.path().canonicalize().unwrap();
+        fs::write(cwd.join("notes.txt"), "").unwrap();
+        assert!(mentioned_files("see notes.txt or notes.txt:abc", &cwd).is_empty());
+    }
+
+    #[test]
+    fn quotes_arguments_with_spaces() {
+        let command = ["grep", "-r", "two words", ""].map(String::from);
+        assert_eq!(display_command(&command), "grep -r 'two words' ''");
+    }
+}
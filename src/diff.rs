@@ -0,0 +1,25 @@
+use crossterm::style::Stylize;
+use similar::{ChangeTag, TextDiff};
+
+/// Prints a colored line diff between `old` and `new`
+pub fn print_diff(old: &str, new: &str) {
+    print!("{}", format_diff(old, new));
+}
+
+/// A colored line diff between `old` and `new`, one line per change
+pub fn format_diff(old: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut output = String::new();
+    for change in diff.iter_all_changes() {
+        let line = change.to_string_lossy();
+        let line = line.trim_end_matches('\n');
+        let line = match change.tag() {
+            ChangeTag::Delete => format!("- {}", line).red().to_string(),
+            ChangeTag::Insert => format!("+ {}", line).green().to_string(),
+            ChangeTag::Equal => format!("  {}", line),
+        };
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
@@ -0,0 +1,48 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+/// Opens `path` in `$VISUAL`/`$EDITOR` and blocks until the editor exits
+pub fn edit_file(path: &Path) -> Result<()> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| default_editor().to_string());
+
+    // Editors are often configured with arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().context("Editor command is empty")?;
+    let status = Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to launch editor '{}'", editor))?;
+    if !status.success() {
+        bail!("Editor '{}' exited with {}", editor, status);
+    }
+    Ok(())
+}
+
+/// Opens a temporary file holding `initial` in the editor and returns what it
+/// contains once the editor exits; `suffix` picks the editor's syntax mode
+pub fn compose(initial: &str, suffix: &str) -> Result<String> {
+    let mut file = tempfile::Builder::new()
+        .prefix("terminus-")
+        .suffix(suffix)
+        .tempfile()?;
+    file.write_all(initial.as_bytes())?;
+    file.flush()?;
+    edit_file(file.path())?;
+    // Editors often replace the file rather than writing to it, so read by path
+    fs::read_to_string(file.path()).context("Edited text is not valid UTF-8")
+}
+
+fn default_editor() -> &'static str {
+    if cfg!(windows) {
+        "notepad"
+    } else {
+        "vi"
+    }
+}
@@ -0,0 +1,224 @@
+use anyhow::{bail, Context, Result};
+use std::io::{stderr, stdin, IsTerminal, Write};
+use std::path::Path;
+use std::process::Command;
+
+use crate::editor;
+use crate::llm::OpenAIClient;
+use crate::markdown::Renderer;
+
+// Largest piece of a diff or log sent in one request; bigger inputs are
+// summarized piece by piece first
+const CHUNK_CHARS: usize = 12_000;
+
+const COMMIT_PROMPT: &str = r#"You write git commit messages in the Conventional Commits format.
+The first line is `type(scope): summary` in the imperative mood, at most 72 characters,
+where type is one of feat, fix, docs, style, refactor, perf, test, build, ci or chore
+and the scope is optional. If the change needs explaining, add a blank line and a body
+wrapped at 72 characters that says what changed and why.
+Reply with the commit message only, without code fences."#;
+
+const CHANGELOG_PROMPT: &str = r#"You write release notes from git commits.
+Group the changes under Markdown headings, in this order: Features, Fixes, Performance,
+Documentation, Other. Leave out empty groups. Write one bullet per user-visible change,
+merging related commits, and end each bullet with the short hashes in parentheses.
+Skip commits that don't affect users, such as formatting or CI tweaks.
+Reply with the release notes only."#;
+
+const SUMMARY_PROMPT: &str = r#"You are reading one part of a larger set of changes.
+Summarize what this part changes as short bullet points, keeping file names, function
+names and commit hashes that a later summary will need."#;
+
+/// Proposes a commit message for the staged changes and commits with it once
+/// the user accepts or edits it. Without a terminal, prints the message instead
+pub async fn commit_message(client: &OpenAIClient) -> Result<()> {
+    let cwd = Path::new(".");
+    let stat = run(cwd, &["diff", "--cached", "--stat"])?;
+    if stat.trim().is_empty() {
+        bail!("Nothing is staged; stage changes with `git add` first");
+    }
+    let diff = run(cwd, &["diff", "--cached", "--no-color", "--no-ext-diff"])?;
+    let parts = split_before(&diff, "diff --git ");
+    let changes = condense(client, &parts).await?;
+    let input = format!("Staged files:\n{}\n{}", stat, changes);
+    let message = client
+        .complete_with_system(&input, COMMIT_PROMPT, None)
+        .await?
+        .content
+        .trim()
+        .to_string();
+
+    if !stdin().is_terminal() {
+        println!("{}", message);
+        return Ok(());
+    }
+    eprintln!("\n{}\n", message);
+    eprint!("Commit with this message? [y]es, [e]dit, [N]o: ");
+    stderr().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    let message = match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => message,
+        "e" | "edit" => editor::compose(&message, ".txt")?.trim().to_string(),
+        _ => {
+            eprintln!("Nothing committed");
+            return Ok(());
+        }
+    };
+    if message.is_empty() {
+        bail!("Empty commit message; nothing committed");
+    }
+
+    // Let hooks and signing prompts use the terminal
+    let status = Command::new("git")
+        .args(["commit", "-m", &message])
+        .status()
+        .context("Failed to run git; is it installed?")?;
+    if !status.success() {
+        bail!("git commit exited with {}", status);
+    }
+    Ok(())
+}
+
+/// Summarizes the commits in `range`, e.g. `v1.0..v1.1`, as grouped release notes
+pub async fn changelog(client: &OpenAIClient, range: &str, render: bool) -> Result<()> {
+    let log = run(
+        Path::new("."),
+        &[
+            "log",
+            "--no-merges",
+            "--format=commit %h%n%s%n%n%b",
+            range,
+            "--",
+        ],
+    )?;
+    if log.trim().is_empty() {
+        bail!("No commits in {}", range);
+    }
+    let parts = split_before(&log, "commit ");
+    let commits = condense(client, &parts).await?;
+    let notes = client
+        .complete_with_system(&commits, CHANGELOG_PROMPT, None)
+        .await?
+        .content;
+
+    if render {
+        let mut renderer = Renderer::new();
+        renderer.push(&notes)?;
+        renderer.finish()?;
+    } else {
+        println!("{}", notes.trim_end());
+    }
+    Ok(())
+}
+
+/// Runs git in `dir` and returns what it printed, failing with git's error output
+pub fn run(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git; is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Joins `parts` into one input if it fits in a request; otherwise packs them
+/// into chunks, summarizes each, and returns the summaries instead
+async fn condense(client: &OpenAIClient, parts: &[&str]) -> Result<String> {
+    let mut chunks: Vec<String> = Vec::new();
+    for part in parts {
+        let part = match part.char_indices().nth(CHUNK_CHARS) {
+            Some((end, _)) => format!("{}\n[... truncated]\n", &part[..end]),
+            None => part.to_string(),
+        };
+        match chunks.last_mut() {
+            Some(chunk) if chunk.len() + part.len() <= CHUNK_CHARS => chunk.push_str(&part),
+            _ => chunks.push(part),
+        }
+    }
+    if chunks.len() <= 1 {
+        return Ok(chunks.pop().unwrap_or_default());
+    }
+
+    let mut summaries = String::from("Summaries of the changes, part by part:\n");
+    for (i, chunk) in chunks.iter().enumerate() {
+        eprintln!("Summarizing part {} of {}...", i + 1, chunks.len());
+        let summary = client
+            .complete_with_system(chunk, SUMMARY_PROMPT, None)
+            .await?;
+        summaries.push_str(&format!("\nPart {}:\n{}\n", i + 1, summary.content.trim()));
+    }
+    Ok(summaries)
+}
+
+/// Splits `text` into pieces that each start at a line beginning with `marker`
+fn split_before<'a>(text: &'a str, marker: &str) -> Vec<&'a str> {
+    let mut starts = text
+        .match_indices(marker)
+        .map(|(i, _)| i)
+        .filter(|i| *i == 0 || text.as_bytes()[i - 1] == b'\n')
+        .collect::<Vec<_>>();
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts
+        .iter()
+        .zip(starts.iter().skip(1).chain([&text.len()]))
+        .map(|(start, end)| &text[*start..*end])
+        .filter(|part| !part.trim().is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_at_markers_starting_a_line() {
+        let diff = "diff --git a/x b/x\n+diff --git inside\ndiff --git a/y b/y\n+y\n";
+        assert_eq!(
+            split_before(diff, "diff --git "),
+            [
+                "diff --git a/x b/x\n+diff --git inside\n",
+                "diff --git a/y b/y\n+y\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_text_before_the_first_marker() {
+        assert_eq!(
+            split_before("preamble\ncommit a\ncommit b\n", "commit "),
+            ["preamble\n", "commit a\n", "commit b\n"]
+        );
+        assert!(split_before("", "commit ").is_empty());
+        assert_eq!(split_before("no markers", "commit "), ["no markers"]);
+    }
+
+    #[tokio::test]
+    async fn joins_parts_that_fit_in_one_request() {
+        // Nothing here is big enough to need a summary, so no request is made
+        let client = OpenAIClient::new("unused", "unused");
+        let condensed = condense(&client, &["commit a\n", "commit b\n"]).await;
+        assert_eq!(condensed.unwrap(), "commit a\ncommit b\n");
+        assert_eq!(condense(&client, &[]).await.unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn truncates_oversized_parts() {
+        let client = OpenAIClient::new("unused", "unused");
+        let part = "x".repeat(CHUNK_CHARS + 10);
+        let condensed = condense(&client, &[&part]).await.unwrap();
+        assert!(condensed.starts_with(&"x".repeat(CHUNK_CHARS)));
+        assert!(condensed.ends_with("\n[... truncated]\n"));
+        assert_eq!(condensed.matches('x').count(), CHUNK_CHARS);
+    }
+}
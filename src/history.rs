@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+use crate::paths;
+use crate::storage;
+
+/// Version history of the default system message or of a persona
+pub struct History {
+    target: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Version {
+    pub version: u32,
+    pub timestamp: String,
+    /// Fingerprint of the system message, as recorded in session context
+    pub fingerprint: String,
+    pub content: String,
+}
+
+impl History {
+    pub fn default_system() -> Self {
+        Self {
+            target: "system".to_string(),
+        }
+    }
+
+    pub fn persona(name: &str) -> Result<Self> {
+        paths::validate_name("Persona", name)?;
+        Ok(Self {
+            target: format!("persona-{}", name),
+        })
+    }
+
+    pub fn versions(&self) -> Result<Vec<Version>> {
+        match storage::read(&self.history_path()?)? {
+            Some(contents) => Ok(serde_json::from_str(&contents)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn version(&self, version: u32) -> Result<Version> {
+        match self.versions()?.into_iter().find(|v| v.version == version) {
+            Some(version) => Ok(version),
+            None => bail!("Version {} not found", version),
+        }
+    }
+
+    /// Records `content` as a new version. `previous` is the content and system
+    /// message stored before, so edits made before history was kept aren't lost.
+    pub fn record(
+        &self,
+        previous: Option<(&str, &str)>,
+        content: &str,
+        system_message: &str,
+    ) -> Result<()> {
+        let _lock = storage::lock()?;
+        let mut versions = self.versions()?;
+        if versions.is_empty() {
+            if let Some((previous, previous_message)) = previous {
+                if !previous.is_empty() && previous != content {
+                    versions.push(Self::new_version(1, previous, previous_message));
+                }
+            }
+        }
+        if versions.last().is_some_and(|last| last.content == content) {
+            return Ok(());
+        }
+        let next = versions.last().map_or(1, |last| last.version + 1);
+        versions.push(Self::new_version(next, content, system_message));
+        storage::write(
+            &self.history_path()?,
+            &serde_json::to_string_pretty(&versions)?,
+        )
+    }
+
+    fn new_version(version: u32, content: &str, system_message: &str) -> Version {
+        Version {
+            version,
+            timestamp: Local::now().to_rfc3339(),
+            fingerprint: if system_message.is_empty() {
+                String::new()
+            } else {
+                storage::fingerprint(system_message)
+            },
+            content: content.to_string(),
+        }
+    }
+
+    fn history_path(&self) -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push("history");
+        path.push(format!("{}.json", self.target));
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_persona_paths() {
+        assert!(History::persona("reviewer").is_ok());
+        for name in ["../settings", "a/b", ".."] {
+            assert!(History::persona(name).is_err(), "{name}");
+        }
+    }
+}
@@ -1,10 +1,23 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize)]
 struct ChatCompletionRequest {
     model: String,
     messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
 }
 
 #[derive(Serialize)]
@@ -16,11 +29,51 @@ struct Message {
 #[derive(Deserialize)]
 struct ChatCompletionResponse {
     choices: Vec<Choice>,
+    usage: Option<Usage>,
 }
 
 #[derive(Deserialize)]
 struct Choice {
     message: ResponseMessage,
+    finish_reason: Option<String>,
+}
+
+// One server-sent event of a streamed response
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    choices: Vec<ChunkChoice>,
+    usage: Option<Usage>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: Delta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Delta {
+    content: Option<String>,
+}
+
+/// Token counts reported by the API
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+/// An earlier prompt and its answer, resent to continue a conversation
+pub struct Turn<'a> {
+    pub prompt: &'a str,
+    pub response: &'a str,
+}
+
+pub struct Completion {
+    pub content: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
 }
 
 #[derive(Deserialize)]
@@ -31,6 +84,8 @@ struct ResponseMessage {
 pub struct OpenAIClient {
     api_key: String,
     model: String,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
     client: reqwest::Client,
 }
 
@@ -39,16 +94,127 @@ impl OpenAIClient {
         Self {
             api_key: api_key.to_string(),
             model: model.to_string(),
+            temperature: None,
+            top_p: None,
             client: reqwest::Client::new(),
         }
     }
 
+    pub fn with_sampling(mut self, temperature: Option<f32>, top_p: Option<f32>) -> Self {
+        self.temperature = temperature;
+        self.top_p = top_p;
+        self
+    }
+
     pub async fn complete_with_system(
         &self,
         prompt: &str,
         system_message: &str,
         memory: Option<&str>,
-    ) -> Result<String> {
+    ) -> Result<Completion> {
+        self.complete_conversation(&[], prompt, system_message, memory)
+            .await
+    }
+
+    pub async fn complete_conversation(
+        &self,
+        history: &[Turn<'_>],
+        prompt: &str,
+        system_message: &str,
+        memory: Option<&str>,
+    ) -> Result<Completion> {
+        let request = self.request(history, prompt, system_message, memory);
+        let response = self
+            .client
+            .post("https://api.openai.com/v1/chat/completions")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<ChatCompletionResponse>()
+            .await?;
+
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .context("The API returned no choices")?;
+        Ok(Completion {
+            content: choice.message.content,
+            finish_reason: choice.finish_reason,
+            usage: response.usage,
+        })
+    }
+
+    /// Like `complete_conversation`, but hands each piece of the answer to
+    /// `on_delta` as it arrives
+    pub async fn stream_conversation(
+        &self,
+        history: &[Turn<'_>],
+        prompt: &str,
+        system_message: &str,
+        memory: Option<&str>,
+        on_delta: &mut dyn FnMut(&str) -> Result<()>,
+    ) -> Result<Completion> {
+        let mut request = self.request(history, prompt, system_message, memory);
+        request.stream = true;
+        request.stream_options = Some(StreamOptions {
+            include_usage: true,
+        });
+        let mut response = self
+            .client
+            .post("https://api.openai.com/v1/chat/completions")
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        let mut completion = Completion {
+            content: String::new(),
+            finish_reason: None,
+            usage: None,
+        };
+        let mut buffer = Vec::new();
+        while let Some(bytes) = response.chunk().await? {
+            buffer.extend_from_slice(&bytes);
+            while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line = buffer.drain(..=end).collect::<Vec<_>>();
+                let line = String::from_utf8(line).context("Invalid UTF-8 in response stream")?;
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+                let data = data.trim();
+                if data == "[DONE]" {
+                    return Ok(completion);
+                }
+                let chunk: ChatCompletionChunk =
+                    serde_json::from_str(data).context("Invalid event in response stream")?;
+                if chunk.usage.is_some() {
+                    completion.usage = chunk.usage;
+                }
+                for choice in chunk.choices {
+                    if let Some(content) = choice.delta.content {
+                        on_delta(&content)?;
+                        completion.content.push_str(&content);
+                    }
+                    if choice.finish_reason.is_some() {
+                        completion.finish_reason = choice.finish_reason;
+                    }
+                }
+            }
+        }
+        Ok(completion)
+    }
+
+    fn request(
+        &self,
+        history: &[Turn<'_>],
+        prompt: &str,
+        system_message: &str,
+        memory: Option<&str>,
+    ) -> ChatCompletionRequest {
         let mut messages = Vec::new();
 
         if !system_message.is_empty() {
@@ -70,26 +236,41 @@ impl OpenAIClient {
             }
         }
 
+        for turn in history {
+            messages.push(Message {
+                role: "user".to_string(),
+                content: turn.prompt.to_string(),
+            });
+            messages.push(Message {
+                role: "assistant".to_string(),
+                content: turn.response.to_string(),
+            });
+        }
+
         messages.push(Message {
             role: "user".to_string(),
             content: prompt.to_string(),
         });
 
-        let request = ChatCompletionRequest {
+        ChatCompletionRequest {
             model: self.model.clone(),
             messages,
-        };
-
-        let response = self
-            .client
-            .post("https://api.openai.com/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request)
-            .send()
-            .await?
-            .json::<ChatCompletionResponse>()
-            .await?;
-
-        Ok(response.choices[0].message.content.clone())
+            temperature: self.temperature,
+            top_p: self.top_p,
+            stream: false,
+            stream_options: None,
+        }
     }
 }
+
+/// The contents of a reply wrapped in a single code fence, for models that
+/// fence JSON even when asked not to
+pub fn strip_code_fence(response: &str) -> &str {
+    let trimmed = response.trim();
+    trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(str::trim)
+        .unwrap_or(trimmed)
+}
@@ -1,260 +1,1359 @@
 use anyhow::Result;
-use clap::Parser;
-use dotenv::dotenv;
-use std::env;
-use std::io::{self, stdin, stdout, Write}; // Add Write trait here
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::engine::ArgValueCandidates;
+use clap_complete::{CompleteEnv, Shell};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{stdin, IsTerminal, Read};
+use std::path::PathBuf;
+use std::time::Instant;
 
 mod boot;
+mod codeblock;
+mod compaction;
+mod completion;
+mod config;
+mod credentials;
+mod crypto;
+mod diagnose;
+mod diff;
+mod editor;
+mod git;
+mod history;
 mod llm;
+mod markdown;
 mod memory;
+mod output;
+mod paths;
+mod persona;
+mod prompttemplate;
+mod repl;
+mod session;
 mod settings;
+mod shellcmd;
+mod storage;
+mod sync;
 mod systemmessage;
+mod template;
 
-use crate::memory::Memory;
+use crate::codeblock::CodeBlock;
+use crate::credentials::Credentials;
+use crate::crypto::Vault;
+use crate::history::History;
+use crate::memory::{Memory, MemoryFormat};
+use crate::output::OutputFormat;
+use crate::persona::Persona;
+use crate::prompttemplate::{PromptTemplate, TemplateParam};
+use crate::session::{ContextRecord, Exchange, Session};
 use crate::settings::Settings;
+use crate::sync::MemorySync;
 use crate::systemmessage::SystemMessage;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// The prompt to send to the LLM
-    #[arg(short, long)]
-    prompt: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Select model to use
-    #[arg(short, long)]
-    select_model: bool,
+    /// Use this persona instead of the active one
+    #[arg(long, global = true, value_name = "NAME", add = ArgValueCandidates::new(completion::persona_names))]
+    persona: Option<String>,
 
-    /// Set system message
-    #[arg(long)]
-    set_system: Option<String>,
+    /// Use this model instead of the configured one
+    #[arg(long, global = true, value_name = "MODEL", add = ArgValueCandidates::new(completion::model_names))]
+    model: Option<String>,
 
-    /// Show current system message
-    #[arg(long)]
-    show_system: bool,
+    /// Don't send memory with this prompt
+    #[arg(long, global = true)]
+    no_memory: bool,
 
-    /// Add new memory entry
-    #[arg(long)]
-    add_memory: Option<String>,
+    /// How answers are written to stdout
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    output: OutputFormat,
 
-    /// Show current memory
-    #[arg(long)]
-    show_memory: bool,
+    /// Print answers as plain text without rendering their Markdown
+    #[arg(long, global = true)]
+    raw: bool,
 
-    /// Toggle memory usage
-    #[arg(long)]
-    toggle_memory: bool,
+    /// Keep settings, memory and sessions in this directory [env: TERMINUS_HOME]
+    #[arg(long, global = true, value_name = "DIR")]
+    home: Option<PathBuf>,
 
-    /// Edit memory file in default text editor
-    #[arg(long)]
-    edit_memory: bool,
+    /// Keep data in a terminus-data directory next to the executable
+    #[arg(long, global = true, conflicts_with = "home")]
+    portable: bool,
+
+    #[command(flatten)]
+    legacy: LegacyFlags,
 }
 
-const AVAILABLE_MODELS: &[&str] = &["gpt-4o", "chatgpt-4o-latest", "gpt-4o-mini"];
-
-async fn handle_menu_choice(choice: &str, settings: &mut Settings) -> Result<bool> {
-    match choice {
-        "1" => {
-            print!("Enter your prompt: ");
-            stdout().flush()?;
-            let mut prompt = String::new();
-            stdin().read_line(&mut prompt)?;
-
-            let api_key = std::env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
-            let client = llm::OpenAIClient::new(&api_key, &settings.model);
-            let system_message = SystemMessage::load()?;
-            let memory_content = if settings.use_memory {
-                Memory::load()?
-            } else {
-                String::new()
-            };
-            let memory = if settings.use_memory {
-                Some(memory_content.as_str())
-            } else {
-                None
-            };
-            let response = client
-                .complete_with_system(&prompt, &system_message, memory)
-                .await?;
-            println!("Response: {}", response);
-        }
-        "2" => {
-            println!("Available models:");
-            for (i, model) in AVAILABLE_MODELS.iter().enumerate() {
-                println!("{}. {}", i + 1, model);
-            }
-            println!("Enter number (1-{}): ", AVAILABLE_MODELS.len());
+// Flags from before subcommands existed, kept working but hidden from help
+#[derive(clap::Args, Debug)]
+struct LegacyFlags {
+    #[arg(short, long, hide = true)]
+    prompt: Option<String>,
+    #[arg(short, long, hide = true)]
+    select_model: bool,
+    #[arg(long, hide = true)]
+    set_system: Option<String>,
+    #[arg(long, hide = true)]
+    show_system: bool,
+    #[arg(long, hide = true)]
+    add_memory: Option<String>,
+    #[arg(long, hide = true)]
+    show_memory: bool,
+    #[arg(long, hide = true)]
+    toggle_memory: bool,
+    #[arg(long, hide = true)]
+    edit_memory: bool,
+    #[arg(long, hide = true)]
+    encrypt_store: bool,
+    #[arg(long, hide = true)]
+    decrypt_store: bool,
+    #[arg(long, hide = true)]
+    show_context: bool,
+}
 
-            let mut input = String::new();
-            std::io::stdin().read_line(&mut input)?;
-            let selection: usize = input.trim().parse()?;
+impl LegacyFlags {
+    /// The subcommand a deprecated flag stands for, warning about the new spelling
+    fn command(self) -> Option<Command> {
+        let (flag, replacement, command) = if let Some(message) = self.set_system {
+            (
+                "--set-system",
+                "system set",
+                Command::System {
+                    action: SystemCommand::Set { message },
+                },
+            )
+        } else if self.show_system {
+            (
+                "--show-system",
+                "system show",
+                Command::System {
+                    action: SystemCommand::Show,
+                },
+            )
+        } else if self.select_model {
+            (
+                "--select-model",
+                "models select",
+                Command::Models {
+                    action: ModelsCommand::Select,
+                },
+            )
+        } else if self.show_memory {
+            (
+                "--show-memory",
+                "memory show",
+                Command::Memory {
+                    action: MemoryCommand::Show,
+                },
+            )
+        } else if let Some(entry) = self.add_memory {
+            (
+                "--add-memory",
+                "memory add",
+                Command::Memory {
+                    action: MemoryCommand::Add { entry },
+                },
+            )
+        } else if self.toggle_memory {
+            (
+                "--toggle-memory",
+                "memory toggle",
+                Command::Memory {
+                    action: MemoryCommand::Toggle,
+                },
+            )
+        } else if self.edit_memory {
+            (
+                "--edit-memory",
+                "memory edit",
+                Command::Memory {
+                    action: MemoryCommand::Edit,
+                },
+            )
+        } else if self.encrypt_store {
+            (
+                "--encrypt-store",
+                "store encrypt",
+                Command::Store {
+                    action: StoreCommand::Encrypt,
+                },
+            )
+        } else if self.decrypt_store {
+            (
+                "--decrypt-store",
+                "store decrypt",
+                Command::Store {
+                    action: StoreCommand::Decrypt,
+                },
+            )
+        } else if let Some(prompt) = self.prompt {
+            (
+                "--prompt",
+                "ask",
+                Command::Ask {
+                    prompt: Some(prompt),
+                    show_context: self.show_context,
+                },
+            )
+        } else {
+            return None;
+        };
+        eprintln!(
+            "Warning: {} is deprecated; use `terminus {}` instead",
+            flag, replacement
+        );
+        Some(command)
+    }
+}
 
-            if selection > 0 && selection <= AVAILABLE_MODELS.len() {
-                settings.model = AVAILABLE_MODELS[selection - 1].to_string();
-                settings.save()?;
-                println!("Model set to: {}", settings.model);
-            } else {
-                println!("Invalid selection");
-            }
+impl Args {
+    /// Settings given as flags, the highest precedence configuration layer
+    fn config_overrides(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut overrides = serde_json::Map::new();
+        if let Some(model) = &self.model {
+            overrides.insert("model".to_string(), model.clone().into());
+        }
+        if let Some(persona) = &self.persona {
+            overrides.insert("persona".to_string(), persona.clone().into());
         }
-        "3" => {
-            print!("Enter new system message: ");
-            stdout().flush()?;
-            let mut message = String::new();
-            stdin().read_line(&mut message)?;
-            SystemMessage::save(&message)?;
-            println!("System message updated successfully");
+        if self.no_memory {
+            overrides.insert("use_memory".to_string(), false.into());
         }
-        "4" => {
-            let message = SystemMessage::load()?;
-            println!("Current system message:\n{}", message);
+        overrides
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Send a single prompt and print the response
+    Ask {
+        /// The prompt to send to the LLM; piped input is attached after it
+        prompt: Option<String>,
+        /// Show which memory entries and system message were sent with the prompt
+        #[arg(long)]
+        show_context: bool,
+    },
+    /// Start an interactive chat (the default without a subcommand)
+    Chat,
+    /// Turn a request into a shell command and run it after confirmation
+    Cmd {
+        /// What the command should do, e.g. "find files larger than 100MB"
+        #[arg(required = true)]
+        request: Vec<String>,
+        /// Add the command to your shell history once it has run
+        #[arg(long)]
+        history: bool,
+    },
+    /// Write commit messages and release notes from the local git repository
+    Git {
+        #[command(subcommand)]
+        action: GitCommand,
+    },
+    /// List and select models
+    Models {
+        #[command(subcommand)]
+        action: ModelsCommand,
+    },
+    /// Manage the memory store
+    Memory {
+        #[command(subcommand)]
+        action: MemoryCommand,
+    },
+    /// Inspect stored sessions
+    Session {
+        #[command(subcommand)]
+        action: SessionCommand,
+    },
+    /// Send a prompt template, or run `-- COMMAND` and diagnose it if it fails
+    Run {
+        /// Template to send
+        #[arg(
+            required_unless_present = "command",
+            conflicts_with = "command",
+            add = ArgValueCandidates::new(completion::template_names)
+        )]
+        template: Option<String>,
+        /// Template parameter as key=value
+        #[arg(long = "var", value_name = "KEY=VALUE", conflicts_with = "command")]
+        vars: Vec<String>,
+        /// Show which memory entries and system message were sent with the prompt
+        #[arg(long)]
+        show_context: bool,
+        /// Command to run, e.g. `terminus run -- cargo build`
+        #[arg(last = true, value_name = "COMMAND")]
+        command: Vec<String>,
+    },
+    /// Manage prompt templates
+    Template {
+        #[command(subcommand)]
+        action: TemplateCommand,
+    },
+    /// Inspect the system message
+    System {
+        #[command(subcommand)]
+        action: SystemCommand,
+    },
+    /// Inspect and change configuration
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommand,
+    },
+    /// Manage named personas
+    Persona {
+        #[command(subcommand)]
+        action: PersonaCommand,
+    },
+    /// Manage the stored API key
+    Credentials {
+        #[command(subcommand)]
+        action: CredentialsCommand,
+    },
+    /// Encrypt or decrypt the store at rest
+    Store {
+        #[command(subcommand)]
+        action: StoreCommand,
+    },
+    /// Print a shell completion script, e.g. `source <(terminus completions bash)`
+    Completions { shell: Shell },
+    /// Print the man page, or write pages for every subcommand into a directory
+    Man {
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ModelsCommand {
+    /// List available models, marking the configured one
+    List,
+    /// Choose a model from a numbered list
+    Select,
+    /// Make a model the default
+    Use {
+        #[arg(add = ArgValueCandidates::new(completion::model_names))]
+        model: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StoreCommand {
+    /// Encrypt memory, sessions and system messages with a passphrase
+    Encrypt,
+    /// Decrypt the store and disable encryption at rest
+    Decrypt,
+}
+
+#[derive(Subcommand, Debug)]
+enum CredentialsCommand {
+    /// Store the API key in the encrypted credentials file
+    Set,
+    /// Remove the stored API key
+    Clear,
+    /// Show which source the API key would be read from
+    Status,
+}
+
+#[derive(clap::Args, Debug)]
+struct PersonaFields {
+    /// Short description shown in listings
+    #[arg(long)]
+    description: Option<String>,
+    /// System message sent with every prompt
+    #[arg(long)]
+    system: Option<String>,
+    /// Default model for this persona
+    #[arg(long, add = ArgValueCandidates::new(completion::model_names))]
+    model: Option<String>,
+    /// Sampling temperature (0-2)
+    #[arg(long)]
+    temperature: Option<f32>,
+    /// Nucleus sampling probability (0-1)
+    #[arg(long)]
+    top_p: Option<f32>,
+}
+
+impl PersonaFields {
+    fn apply(self, persona: &mut Persona) {
+        if let Some(description) = self.description {
+            persona.description = description;
         }
-        "5" => {
-            print!("Enter memory entry: ");
-            stdout().flush()?;
-            let mut entry = String::new();
-            stdin().read_line(&mut entry)?;
-            Memory::append(&entry)?;
-            println!("Memory entry added successfully");
+        if let Some(system) = self.system {
+            persona.system_message = system;
         }
-        "6" => {
-            let memory = Memory::load()?;
-            println!("Current memory:\n{}", memory);
+        if let Some(model) = self.model {
+            persona.model = Some(model);
         }
-        "7" => {
-            settings.use_memory = !settings.use_memory;
-            settings.save()?;
-            println!(
-                "Memory usage: {}",
-                if settings.use_memory {
-                    "enabled"
-                } else {
-                    "disabled"
-                }
-            );
+        if let Some(temperature) = self.temperature {
+            persona.temperature = Some(temperature);
         }
-        "8" => {
-            Memory::edit()?;
+        if let Some(top_p) = self.top_p {
+            persona.top_p = Some(top_p);
         }
-        "9" => return Ok(true),
-        _ => println!("Invalid choice, please try again"),
     }
-    Ok(false)
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    dotenv().ok();
-    let args = Args::parse();
-    let mut settings = Settings::load()?;
+#[derive(Subcommand, Debug)]
+enum PersonaCommand {
+    /// List personas
+    List,
+    /// Show a persona
+    Show {
+        #[arg(add = ArgValueCandidates::new(completion::persona_names))]
+        name: String,
+    },
+    /// Create a new persona
+    Create {
+        name: String,
+        #[command(flatten)]
+        fields: PersonaFields,
+    },
+    /// Change fields of an existing persona
+    Edit {
+        #[arg(add = ArgValueCandidates::new(completion::persona_names))]
+        name: String,
+        #[command(flatten)]
+        fields: PersonaFields,
+    },
+    /// Delete a persona
+    Delete {
+        #[arg(add = ArgValueCandidates::new(completion::persona_names))]
+        name: String,
+    },
+    /// Make a persona active; without a name the default system message is used
+    Use {
+        #[arg(add = ArgValueCandidates::new(completion::persona_names))]
+        name: Option<String>,
+    },
+}
 
-    // If no arguments provided, run interactive mode
-    if args.prompt.is_none()
-        && !args.select_model
-        && args.set_system.is_none()
-        && !args.show_system
-        && args.add_memory.is_none()
-        && !args.show_memory
-        && !args.toggle_memory
-        && !args.edit_memory
-    {
-        boot::boot_sequence();
-
-        loop {
-            let choice = boot::show_menu()?;
-            if handle_menu_choice(&choice, &mut settings).await? {
-                break;
-            }
+#[derive(Subcommand, Debug)]
+enum TemplateCommand {
+    /// List prompt templates
+    List,
+    /// Show a prompt template
+    Show {
+        #[arg(add = ArgValueCandidates::new(completion::template_names))]
+        name: String,
+    },
+    /// Create a prompt template using {{param}} placeholders
+    Create {
+        name: String,
+        /// Prompt text
+        #[arg(long)]
+        prompt: String,
+        #[arg(long, default_value = "")]
+        description: String,
+        /// Declare a parameter, optionally with a default
+        #[arg(long = "param", value_name = "NAME[=DEFAULT]")]
+        params: Vec<String>,
+        /// Persona to use with this template
+        #[arg(long, add = ArgValueCandidates::new(completion::persona_names))]
+        persona: Option<String>,
+        /// Model to use with this template
+        #[arg(long, add = ArgValueCandidates::new(completion::model_names))]
+        model: Option<String>,
+    },
+    /// Delete a prompt template
+    Delete {
+        #[arg(add = ArgValueCandidates::new(completion::template_names))]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective value of a setting
+    Get { key: String },
+    /// Store a setting in the global settings file
+    Set { key: String, value: String },
+    /// Reset a setting in the global settings file to its default
+    Unset { key: String },
+    /// List every setting with its effective value and source
+    List,
+    /// Open the global settings file in $EDITOR
+    Edit,
+    /// Show which configuration layer supplied a value
+    Explain {
+        /// Explain every key when omitted
+        key: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SystemCommand {
+    /// Show the system message as stored
+    Show,
+    /// Replace the system message of the active persona or the default
+    Set { message: String },
+    /// Show the system message with its template variables rendered
+    Preview,
+    /// List saved versions of the system message
+    History {
+        /// Persona to inspect instead of the active one
+        #[arg(long, add = ArgValueCandidates::new(completion::persona_names))]
+        persona: Option<String>,
+    },
+    /// Show the changes between two versions
+    Diff {
+        from: u32,
+        /// Defaults to the latest version
+        to: Option<u32>,
+        #[arg(long, add = ArgValueCandidates::new(completion::persona_names))]
+        persona: Option<String>,
+    },
+    /// Restore an earlier version
+    Rollback {
+        version: u32,
+        #[arg(long, add = ArgValueCandidates::new(completion::persona_names))]
+        persona: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SessionCommand {
+    /// List stored sessions
+    List,
+    /// Show the exchanges and injected context of a session
+    Show {
+        #[arg(add = ArgValueCandidates::new(completion::session_ids))]
+        id: String,
+    },
+    /// List the code blocks in a session's last response, or save, append or run one
+    Code {
+        /// Block number from the list
+        number: Option<usize>,
+        /// Session to use instead of the most recent one
+        #[arg(long, value_name = "ID", add = ArgValueCandidates::new(completion::session_ids))]
+        session: Option<String>,
+        /// Write the block to this file
+        #[arg(long, value_name = "PATH", requires = "number", conflicts_with_all = ["append", "run"])]
+        save: Option<PathBuf>,
+        /// Append the block to this file
+        #[arg(long, value_name = "PATH", requires = "number", conflicts_with = "run")]
+        append: Option<PathBuf>,
+        /// Run a shell or Python block after confirmation
+        #[arg(long, requires = "number")]
+        run: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum GitCommand {
+    /// Propose a Conventional Commits message for the staged changes and commit with it
+    CommitMsg,
+    /// Summarize a commit range into release notes grouped by kind of change
+    Changelog {
+        /// Commits to include, e.g. v1.0..v1.1
+        range: String,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum MemoryCommand {
+    /// Print the memory store
+    Show,
+    /// Add a memory entry
+    Add { entry: String },
+    /// Open the memory store in $EDITOR (not while the store is encrypted)
+    Edit,
+    /// Turn sending memory with prompts on or off
+    Toggle,
+    /// Merge duplicates and flag contradictions with the model's help
+    Compact,
+    /// Export the memory store
+    Export {
+        #[arg(long, value_enum, default_value = "json")]
+        format: MemoryFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Import entries from a JSON or Markdown export
+    Import {
+        file: PathBuf,
+        /// Defaults to the file extension
+        #[arg(long, value_enum)]
+        format: Option<MemoryFormat>,
+    },
+    /// Keep the memory store in a local git repository
+    ///
+    /// Pulling merges entry by entry, matching entries by their text. An entry
+    /// edited differently on both sides since the last pull can't be matched
+    /// up, so it shows up twice afterwards, once with each edit. Remove the
+    /// copy you don't want with `terminus memory edit`.
+    Sync {
+        #[command(subcommand)]
+        action: SyncCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SyncCommand {
+    /// Start committing memory changes to git
+    Enable {
+        /// Path of the repository to pull from
+        #[arg(long)]
+        remote: Option<String>,
+    },
+    /// Stop committing memory changes
+    Disable,
+    /// Merge memory entries from the remote repository
+    ///
+    /// Entries are matched by their text, so an entry edited differently on
+    /// both sides since the last pull is kept twice, once with each edit.
+    Pull {
+        /// Overrides the configured remote
+        remote: Option<String>,
+    },
+}
+
+pub const AVAILABLE_MODELS: &[&str] = &["gpt-4o", "chatgpt-4o-latest", "gpt-4o-mini"];
+
+async fn send_prompt(
+    prompt: &str,
+    settings: &Settings,
+    persona: Option<&Persona>,
+    model: Option<&str>,
+    session: &mut Session,
+    renderer: Option<&mut markdown::Renderer>,
+) -> Result<Exchange> {
+    let model = model
+        .map(str::to_string)
+        .or_else(|| persona.and_then(|persona| persona.model.clone()))
+        .unwrap_or_else(|| settings.model.clone());
+    let client = llm::OpenAIClient::new(&Credentials::api_key()?, &model).with_sampling(
+        persona
+            .and_then(|persona| persona.temperature)
+            .or(settings.temperature.map(|t| t as f32)),
+        persona
+            .and_then(|persona| persona.top_p)
+            .or(settings.top_p.map(|p| p as f32)),
+    );
+    let raw_system_message = match persona {
+        Some(persona) => persona.system_message.clone(),
+        None => SystemMessage::load_raw()?,
+    };
+    let system_message = SystemMessage::render(&raw_system_message)?;
+    let memory_entries = if settings.use_memory {
+        Memory::entries()?
+    } else {
+        Vec::new()
+    };
+    let memory_content = memory_entries
+        .iter()
+        .map(|entry| entry.content.as_str())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let memory = if settings.use_memory {
+        Some(memory_content.as_str())
+    } else {
+        None
+    };
+    // Earlier exchanges in the session make this a continuing conversation
+    let history = session
+        .exchanges
+        .iter()
+        .map(|exchange| llm::Turn {
+            prompt: &exchange.prompt,
+            response: &exchange.response,
+        })
+        .collect::<Vec<_>>();
+    let started = Instant::now();
+    let completion = match renderer {
+        Some(renderer) => {
+            let completion = client
+                .stream_conversation(&history, prompt, &system_message, memory, &mut |delta| {
+                    renderer.push(delta)
+                })
+                .await?;
+            renderer.finish()?;
+            completion
         }
-        return Ok(());
-    }
+        None => {
+            client
+                .complete_conversation(&history, prompt, &system_message, memory)
+                .await?
+        }
+    };
+    let latency_ms = started.elapsed().as_millis() as u64;
 
-    let api_key = env::var("OPENAI_API_KEY").expect("OPENAI_API_KEY must be set");
+    let exchange = Exchange {
+        timestamp: chrono::Local::now().to_rfc3339(),
+        model,
+        prompt: prompt.to_string(),
+        response: completion.content,
+        context: ContextRecord {
+            persona: persona.map(|persona| persona.name.clone()),
+            system_message_version: (!raw_system_message.is_empty())
+                .then(|| storage::fingerprint(&raw_system_message)),
+            memory_enabled: settings.use_memory,
+            memory_entries,
+        },
+        finish_reason: completion.finish_reason,
+        usage: completion.usage,
+        latency_ms,
+    };
+    session.record(exchange.clone())?;
+    Ok(exchange)
+}
 
-    if let Some(message) = args.set_system {
-        SystemMessage::save(&message)?;
-        println!("System message updated successfully");
-        return Ok(());
+/// Everything piped to stdin, or `None` when stdin is a terminal
+fn piped_input() -> Result<Option<String>> {
+    if stdin().is_terminal() {
+        return Ok(None);
     }
+    let mut input = String::new();
+    stdin().read_to_string(&mut input)?;
+    Ok(Some(input))
+}
 
-    if args.show_system {
-        let message = SystemMessage::load()?;
-        println!("Current system message:\n{}", message);
-        return Ok(());
+fn select_model(settings: &mut Settings) -> Result<()> {
+    println!("Available models:");
+    for (i, model) in AVAILABLE_MODELS.iter().enumerate() {
+        println!("{}. {}", i + 1, model);
     }
+    println!("Enter number (1-{}): ", AVAILABLE_MODELS.len());
 
-    if args.select_model {
-        println!("Available models:");
-        for (i, model) in AVAILABLE_MODELS.iter().enumerate() {
-            println!("{}. {}", i + 1, model);
-        }
-        println!("Enter number (1-{}): ", AVAILABLE_MODELS.len());
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input)?;
+    let selection: usize = input.trim().parse()?;
 
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
-        let selection: usize = input.trim().parse()?;
+    if selection > 0 && selection <= AVAILABLE_MODELS.len() {
+        settings.model = AVAILABLE_MODELS[selection - 1].to_string();
+        Settings::update(|global| global.model = settings.model.clone())?;
+        eprintln!("Model set to: {}", settings.model);
+    } else {
+        println!("Invalid selection");
+    }
+    Ok(())
+}
 
-        if selection > 0 && selection <= AVAILABLE_MODELS.len() {
-            settings.model = AVAILABLE_MODELS[selection - 1].to_string();
-            settings.save()?;
-            println!("Model set to: {}", settings.model);
-            return Ok(());
+fn toggle_memory(settings: &mut Settings) -> Result<()> {
+    settings.use_memory = !settings.use_memory;
+    Settings::update(|global| global.use_memory = settings.use_memory)?;
+    eprintln!(
+        "Memory usage: {}",
+        if settings.use_memory {
+            "enabled"
         } else {
-            println!("Invalid selection");
-            return Ok(());
+            "disabled"
         }
-    }
+    );
+    Ok(())
+}
 
-    if args.show_memory {
-        let memory = Memory::load()?;
-        println!("Current memory:\n{}", memory);
-        return Ok(());
+fn edit_memory() -> Result<()> {
+    if Memory::edit()? {
+        eprintln!("Memory updated successfully");
+    } else {
+        eprintln!("Memory left unchanged");
     }
+    Ok(())
+}
 
-    if let Some(memory_entry) = args.add_memory {
-        Memory::append(&memory_entry)?;
-        println!("Memory entry added successfully");
-        return Ok(());
+fn use_persona(name: Option<String>) -> Result<()> {
+    if let Some(name) = &name {
+        Persona::load(name)?;
     }
-
-    if args.toggle_memory {
-        settings.use_memory = !settings.use_memory;
-        settings.save()?;
-        println!(
-            "Memory usage: {}",
-            if settings.use_memory {
-                "enabled"
-            } else {
-                "disabled"
-            }
-        );
-        return Ok(());
+    let settings = Settings::update(|settings| settings.persona = name)?;
+    match &settings.persona {
+        Some(name) => eprintln!("Active persona: {}", name),
+        None => eprintln!("Using the default system message"),
     }
+    Ok(())
+}
+
+/// History of the named persona, else the active one, else the default system message
+fn system_history(persona: Option<String>) -> Result<(History, Option<String>)> {
+    let name = match persona {
+        Some(name) => Some(name),
+        None => Settings::load()?.persona,
+    };
+    Ok(match &name {
+        Some(name) => (History::persona(name)?, Some(name.clone())),
+        None => (History::default_system(), None),
+    })
+}
+
+async fn run_template(
+    template: &PromptTemplate,
+    vars: &HashMap<String, String>,
+    settings: &Settings,
+    persona_override: Option<&Persona>,
+    session: &mut Session,
+    renderer: Option<&mut markdown::Renderer>,
+) -> Result<Exchange> {
+    let prompt = template.render(vars)?;
+    let persona = match (persona_override, &template.persona) {
+        (Some(persona), _) => Some(persona.clone()),
+        (None, Some(name)) => Some(Persona::load(name)?),
+        (None, None) => Persona::active()?,
+    };
+    send_prompt(
+        &prompt,
+        settings,
+        persona.as_ref(),
+        template.model.as_deref(),
+        session,
+        renderer,
+    )
+    .await
+}
 
-    if args.edit_memory {
-        Memory::edit()?;
-        return Ok(());
+#[tokio::main]
+async fn main() -> Result<()> {
+    Credentials::load_dotenv();
+    completion::use_completed_home();
+    CompleteEnv::with_factory(Args::command)
+        .var(completion::COMPLETE_VAR)
+        .complete();
+    let args = Args::parse();
+    if let Some(home) = &args.home {
+        paths::set_home(home.clone())?;
+    } else if args.portable {
+        paths::set_home(paths::enable_portable()?)?;
     }
+    config::set_cli_overrides(args.config_overrides());
+    let mut settings = Settings::load()?;
+    let persona_override = match &args.persona {
+        Some(name) => Some(Persona::load(name)?),
+        None => None,
+    };
 
-    if let Some(prompt) = args.prompt {
-        let client = llm::OpenAIClient::new(&api_key, &settings.model);
-        let system_message = SystemMessage::load()?;
-        let memory_content = if settings.use_memory {
-            Memory::load()?
-        } else {
-            String::new()
-        };
-        let memory = if settings.use_memory {
-            Some(memory_content.as_str())
-        } else {
-            None
-        };
-        let response = client
-            .complete_with_system(&prompt, &system_message, memory)
+    // Piped input without a subcommand is a prompt, not a menu session
+    let command = match args.command {
+        Some(command) => command,
+        None => match args.legacy.command() {
+            Some(command) => command,
+            None if stdin().is_terminal() => Command::Chat,
+            None => Command::Ask {
+                prompt: None,
+                show_context: false,
+            },
+        },
+    };
+
+    match command {
+        Command::Ask {
+            prompt,
+            show_context,
+        } => {
+            let input = piped_input()?.filter(|input| !input.trim().is_empty());
+            let prompt = match (prompt, input) {
+                (Some(prompt), Some(input)) => format!("{}\n\n{}", prompt, input.trim_end()),
+                (Some(prompt), None) => prompt,
+                (None, Some(input)) => input.trim().to_string(),
+                (None, None) => {
+                    anyhow::bail!("No prompt given; pass one as an argument or pipe it on stdin")
+                }
+            };
+            let persona = match persona_override {
+                Some(persona) => Some(persona),
+                None => Persona::active()?,
+            };
+            let mut session = Session::new();
+            let mut renderer = output::renders(args.output, args.raw).then(markdown::Renderer::new);
+            let exchange = send_prompt(
+                &prompt,
+                &settings,
+                persona.as_ref(),
+                None,
+                &mut session,
+                renderer.as_mut(),
+            )
             .await?;
-        println!("Response: {}", response);
-    } else {
-        println!("Please provide a prompt with --prompt or select a model with --select-model");
+            output::print_exchange(
+                &exchange,
+                &session.id,
+                args.output,
+                show_context,
+                renderer.is_some(),
+            )?;
+        }
+        Command::Cmd { request, history } => {
+            let client = llm::OpenAIClient::new(&Credentials::api_key()?, &settings.model);
+            shellcmd::run(
+                &client,
+                &request.join(" "),
+                history || settings.shell_history,
+            )
+            .await?;
+        }
+        Command::Git { action } => {
+            let client = llm::OpenAIClient::new(&Credentials::api_key()?, &settings.model);
+            match action {
+                GitCommand::CommitMsg => git::commit_message(&client).await?,
+                GitCommand::Changelog { range } => {
+                    git::changelog(&client, &range, output::renders(args.output, args.raw)).await?
+                }
+            }
+        }
+        Command::Chat => {
+            boot::boot_sequence();
+            repl::run(settings, persona_override, args.raw).await?;
+        }
+        Command::Models { action } => match action {
+            ModelsCommand::List => {
+                for model in AVAILABLE_MODELS {
+                    let marker = if settings.model == *model { "*" } else { " " };
+                    println!("{} {}", marker, model);
+                }
+            }
+            ModelsCommand::Select => select_model(&mut settings)?,
+            ModelsCommand::Use { model } => {
+                if !AVAILABLE_MODELS.contains(&model.as_str()) {
+                    eprintln!(
+                        "Warning: {} is not a known model ({})",
+                        model,
+                        AVAILABLE_MODELS.join(", ")
+                    );
+                }
+                Settings::update(|global| global.model = model.clone())?;
+                eprintln!("Model set to: {}", model);
+            }
+        },
+        Command::Completions { shell } => completion::write_registration(shell)?,
+        Command::Man { dir } => completion::write_man_pages(Args::command(), dir.as_deref())?,
+        Command::Store { action } => match action {
+            StoreCommand::Encrypt => {
+                if Vault::is_enabled()? {
+                    eprintln!("Store is already encrypted");
+                    return Ok(());
+                }
+                let passphrase = Vault::prompt_new_passphrase()?;
+                let count = storage::encrypt_all(&passphrase)?;
+                eprintln!("Store encrypted successfully ({} files)", count);
+            }
+            StoreCommand::Decrypt => {
+                if !Vault::is_enabled()? {
+                    eprintln!("Store is not encrypted");
+                    return Ok(());
+                }
+                if Credentials::is_stored()? {
+                    anyhow::bail!(
+                        "The store holds an API key; remove it with `terminus credentials clear` before decrypting"
+                    );
+                }
+                let count = storage::decrypt_all()?;
+                eprintln!("Store decrypted successfully ({} files)", count);
+            }
+        },
+        Command::Memory { action } => match action {
+            MemoryCommand::Show => {
+                let memory = Memory::load()?;
+                println!("Current memory:\n{}", memory);
+            }
+            MemoryCommand::Add { entry } => {
+                Memory::append(&entry)?;
+                eprintln!("Memory entry added successfully");
+            }
+            MemoryCommand::Edit => edit_memory()?,
+            MemoryCommand::Toggle => toggle_memory(&mut settings)?,
+            MemoryCommand::Compact => {
+                let client = llm::OpenAIClient::new(&Credentials::api_key()?, &settings.model);
+                compaction::compact(&client).await?;
+            }
+            MemoryCommand::Export { format, output } => {
+                let export = Memory::export(format)?;
+                match output {
+                    Some(path) => {
+                        fs::write(&path, export)?;
+                        eprintln!("Memory exported to {}", path.display());
+                    }
+                    None => print!("{}", export),
+                }
+            }
+            MemoryCommand::Import { file, format } => {
+                let format = match format {
+                    Some(format) => format,
+                    None => match file.extension().and_then(|ext| ext.to_str()) {
+                        Some("md") | Some("markdown") => MemoryFormat::Markdown,
+                        _ => MemoryFormat::Json,
+                    },
+                };
+                let added = Memory::import(&fs::read_to_string(&file)?, format)?;
+                eprintln!("Imported {} new memory entries", added);
+            }
+            MemoryCommand::Sync { action } => match action {
+                SyncCommand::Enable { remote } => {
+                    MemorySync::enable(remote)?;
+                    eprintln!("Memory sync enabled");
+                }
+                SyncCommand::Disable => {
+                    MemorySync::disable()?;
+                    eprintln!("Memory sync disabled");
+                }
+                SyncCommand::Pull { remote } => {
+                    let Some(remote) = remote.or(settings.memory_sync_remote.clone()) else {
+                        anyhow::bail!("No remote configured; pass one or use `memory sync enable --remote PATH`");
+                    };
+                    let summary = MemorySync::pull(&remote)?;
+                    eprintln!(
+                        "Memory merged from {} ({} added, {} removed)",
+                        remote, summary.added, summary.removed
+                    );
+                }
+            },
+        },
+        Command::Run {
+            template,
+            vars,
+            show_context,
+            command,
+        } => {
+            let Some(template) = template else {
+                let persona = match persona_override {
+                    Some(persona) => Some(persona),
+                    None => Persona::active()?,
+                };
+                let status =
+                    diagnose::run(&command, &settings, persona.as_ref(), args.output, args.raw)
+                        .await?;
+                if !status.success() {
+                    std::process::exit(status.code().unwrap_or(1));
+                }
+                return Ok(());
+            };
+            let template = PromptTemplate::load(&template)?;
+            let vars = prompttemplate::parse_vars(&vars)?;
+            let mut session = Session::new();
+            let mut renderer = output::renders(args.output, args.raw).then(markdown::Renderer::new);
+            let exchange = run_template(
+                &template,
+                &vars,
+                &settings,
+                persona_override.as_ref(),
+                &mut session,
+                renderer.as_mut(),
+            )
+            .await?;
+            output::print_exchange(
+                &exchange,
+                &session.id,
+                args.output,
+                show_context,
+                renderer.is_some(),
+            )?;
+        }
+        Command::Template { action } => match action {
+            TemplateCommand::List => {
+                for template in PromptTemplate::list()? {
+                    println!("{} - {}", template.name, template.description);
+                }
+            }
+            TemplateCommand::Show { name } => PromptTemplate::load(&name)?.print(),
+            TemplateCommand::Create {
+                name,
+                prompt,
+                description,
+                params,
+                persona,
+                model,
+            } => {
+                if PromptTemplate::exists(&name)? {
+                    anyhow::bail!("Template already exists: {}", name);
+                }
+                if let Some(persona) = &persona {
+                    Persona::load(persona)?;
+                }
+                let template = PromptTemplate {
+                    name: name.clone(),
+                    description,
+                    prompt,
+                    params: params
+                        .iter()
+                        .map(|param| match param.split_once('=') {
+                            Some((name, default)) => TemplateParam {
+                                name: name.to_string(),
+                                description: String::new(),
+                                default: Some(default.to_string()),
+                            },
+                            None => TemplateParam {
+                                name: param.to_string(),
+                                description: String::new(),
+                                default: None,
+                            },
+                        })
+                        .collect(),
+                    persona,
+                    model,
+                };
+                template.save()?;
+                eprintln!("Template created: {}", name);
+            }
+            TemplateCommand::Delete { name } => {
+                PromptTemplate::delete(&name)?;
+                eprintln!("Template deleted: {}", name);
+            }
+        },
+        Command::System { action } => match action {
+            SystemCommand::Show => {
+                let message = SystemMessage::load_raw()?;
+                println!("Current system message:\n{}", message);
+            }
+            SystemCommand::Set { message } => {
+                SystemMessage::save(&message)?;
+                eprintln!("System message updated successfully");
+            }
+            SystemCommand::Preview => {
+                let message = match &persona_override {
+                    Some(persona) => SystemMessage::render(&persona.system_message)?,
+                    None => SystemMessage::load()?,
+                };
+                println!("{}", message);
+            }
+            SystemCommand::History { persona } => {
+                let (history, _) = system_history(persona.or(args.persona.clone()))?;
+                let versions = history.versions()?;
+                if versions.is_empty() {
+                    eprintln!("No saved versions yet");
+                }
+                let latest = versions.last().map(|v| v.version);
+                for version in versions.iter().rev() {
+                    println!(
+                        "v{}  {}  {}{}",
+                        version.version,
+                        chrono::DateTime::parse_from_rfc3339(&version.timestamp)
+                            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                            .unwrap_or_else(|_| version.timestamp.clone()),
+                        version.fingerprint,
+                        if Some(version.version) == latest {
+                            "  (current)"
+                        } else {
+                            ""
+                        }
+                    );
+                }
+            }
+            SystemCommand::Diff { from, to, persona } => {
+                let (history, _) = system_history(persona.or(args.persona.clone()))?;
+                let from = history.version(from)?;
+                let to = match to {
+                    Some(to) => history.version(to)?,
+                    None => match history.versions()?.pop() {
+                        Some(latest) => latest,
+                        None => anyhow::bail!("No saved versions yet"),
+                    },
+                };
+                println!("--- v{}\n+++ v{}", from.version, to.version);
+                diff::print_diff(&from.content, &to.content);
+            }
+            SystemCommand::Rollback { version, persona } => {
+                let (history, name) = system_history(persona.or(args.persona.clone()))?;
+                let restored = history.version(version)?;
+                match name {
+                    Some(name) => {
+                        let persona: Persona = serde_json::from_str(&restored.content)?;
+                        if persona.name != name {
+                            anyhow::bail!(
+                                "Version {} belongs to persona {}",
+                                version,
+                                persona.name
+                            );
+                        }
+                        persona.save()?;
+                        eprintln!("Persona {} rolled back to version {}", name, version);
+                    }
+                    None => {
+                        SystemMessage::save_default(&restored.content)?;
+                        eprintln!("System message rolled back to version {}", version);
+                    }
+                }
+            }
+        },
+        Command::Config { action } => match action {
+            ConfigCommand::Get { key } => {
+                let key = config::find_key(&key)?;
+                let resolved = config::resolve()?;
+                let (_, value) = resolved.layers[key.name].last().unwrap();
+                println!("{}", config::display(value));
+            }
+            ConfigCommand::Set { key, value } => {
+                let value = config::display(&config::set(&key, &value)?);
+                if key == "model" && !AVAILABLE_MODELS.contains(&value.as_str()) {
+                    eprintln!(
+                        "Warning: {} is not a known model ({})",
+                        value,
+                        AVAILABLE_MODELS.join(", ")
+                    );
+                }
+                eprintln!("{} set to {}", key, value);
+            }
+            ConfigCommand::Unset { key } => {
+                let value = config::unset(&key)?;
+                eprintln!("{} reset to {}", key, config::display(&value));
+            }
+            ConfigCommand::List => {
+                let resolved = config::resolve()?;
+                for key in config::KEYS {
+                    let (source, value) = resolved.layers[key.name].last().unwrap();
+                    println!("{} = {}", key.name, config::display(value));
+                    println!("  {} ({})", key.description, key.kind);
+                    println!("  from {}", source);
+                }
+            }
+            ConfigCommand::Edit => {
+                config::edit()?;
+                eprintln!("Settings updated successfully");
+            }
+            ConfigCommand::Explain { key } => {
+                let resolved = config::resolve()?;
+                let keys = match key {
+                    Some(key) => vec![config::find_key(&key)?.name.to_string()],
+                    None => resolved.layers.keys().cloned().collect(),
+                };
+                for key in keys {
+                    let layers = &resolved.layers[&key];
+                    let (source, value) = layers.last().unwrap();
+                    println!("{} = {}", key, value);
+                    println!("  from {}", source);
+                    for (source, value) in layers.iter().rev().skip(1) {
+                        println!("  overrides {} = {}", source, value);
+                    }
+                }
+            }
+        },
+        Command::Credentials { action } => match action {
+            CredentialsCommand::Set => {
+                let api_key = if stdin().is_terminal() {
+                    rpassword::prompt_password("API key: ")?
+                } else {
+                    let mut line = String::new();
+                    stdin().read_line(&mut line)?;
+                    line
+                };
+                Credentials::store(api_key.trim())?;
+                eprintln!("API key stored successfully");
+            }
+            CredentialsCommand::Clear => {
+                if Credentials::clear()? {
+                    eprintln!("Stored API key removed");
+                } else {
+                    eprintln!("No API key is stored");
+                }
+            }
+            CredentialsCommand::Status => match Credentials::provider()? {
+                Some(provider) => println!("API key is read from {}", provider),
+                None => println!("No API key configured"),
+            },
+        },
+        Command::Persona { action } => match action {
+            PersonaCommand::List => {
+                for persona in Persona::list()? {
+                    let marker = if settings.persona.as_deref() == Some(persona.name.as_str()) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{} {} - {}", marker, persona.name, persona.description);
+                }
+            }
+            PersonaCommand::Show { name } => Persona::load(&name)?.print(),
+            PersonaCommand::Create { name, fields } => {
+                if Persona::exists(&name)? {
+                    anyhow::bail!("Persona already exists: {}", name);
+                }
+                let mut persona = Persona::new(&name)?;
+                fields.apply(&mut persona);
+                persona.save()?;
+                eprintln!("Persona created: {}", name);
+            }
+            PersonaCommand::Edit { name, fields } => {
+                let mut persona = Persona::load(&name)?;
+                fields.apply(&mut persona);
+                persona.save()?;
+                eprintln!("Persona updated: {}", name);
+            }
+            PersonaCommand::Delete { name } => {
+                Persona::delete(&name)?;
+                eprintln!("Persona deleted: {}", name);
+            }
+            PersonaCommand::Use { name } => use_persona(name)?,
+        },
+        Command::Session { action } => match action {
+            SessionCommand::List => {
+                for id in Session::list()? {
+                    println!("{}", id);
+                }
+            }
+            SessionCommand::Show { id } => {
+                let session = Session::load(&id)?;
+                println!("Session {} (started {})", session.id, session.created);
+                for exchange in &session.exchanges {
+                    println!("\n[{}] {}", exchange.timestamp, exchange.model);
+                    println!("Prompt: {}", exchange.prompt);
+                    println!("Response: {}", exchange.response);
+                    exchange.context.print();
+                }
+            }
+            SessionCommand::Code {
+                number,
+                session,
+                save,
+                append,
+                run,
+            } => {
+                let id = match session {
+                    Some(id) => id,
+                    None => match Session::list()?.pop() {
+                        Some(id) => id,
+                        None => anyhow::bail!("No sessions yet"),
+                    },
+                };
+                let mut session = Session::load(&id)?;
+                let Some(exchange) = session.last_exchange() else {
+                    anyhow::bail!("Session {} has no responses", id);
+                };
+                let blocks = CodeBlock::extract(&exchange.response);
+                let Some(number) = number else {
+                    CodeBlock::print_list(&blocks);
+                    return Ok(());
+                };
+                let block = CodeBlock::select(&blocks, number)?;
+                if let Some(path) = save {
+                    block.save(&path)?;
+                    eprintln!("Code block {} saved to {}", number, path.display());
+                } else if let Some(path) = append {
+                    block.append(&path)?;
+                    eprintln!("Code block {} appended to {}", number, path.display());
+                } else if run {
+                    let Some(result) = block.run()? else {
+                        return Ok(());
+                    };
+                    result.print();
+                    // Carry on the same conversation with what the block printed
+                    if let Some(prompt) = result.follow_up(number)? {
+                        let persona = match persona_override {
+                            Some(persona) => Some(persona),
+                            None => Persona::active()?,
+                        };
+                        let mut renderer =
+                            output::renders(args.output, args.raw).then(markdown::Renderer::new);
+                        let exchange = send_prompt(
+                            &prompt,
+                            &settings,
+                            persona.as_ref(),
+                            None,
+                            &mut session,
+                            renderer.as_mut(),
+                        )
+                        .await?;
+                        output::print_exchange(
+                            &exchange,
+                            &session.id,
+                            args.output,
+                            false,
+                            renderer.is_some(),
+                        )?;
+                    }
+                } else {
+                    print!("{}", block.code);
+                }
+            }
+        },
     }
 
     Ok(())
@@ -0,0 +1,346 @@
+use anyhow::Result;
+use crossterm::style::Stylize;
+use crossterm::terminal;
+use std::io::{stdout, Write};
+use std::mem;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::parsing::SyntaxSet;
+use syntect::util::as_24_bit_terminal_escaped;
+use textwrap::core::display_width;
+
+const THEME: &str = "base16-ocean.dark";
+
+// Loading the bundled syntaxes takes a moment, so only do it once a code
+// block actually shows up
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+/// Writes Markdown to the terminal as styled text. Input can arrive in pieces
+/// of any size; each line is rendered as soon as it is complete, except table
+/// rows, which wait for the end of the table so the columns line up
+pub struct Renderer {
+    width: usize,
+    pending: String,
+    code: Option<CodeBlock>,
+    table: Vec<String>,
+}
+
+struct CodeBlock {
+    fence: String,
+    highlighter: HighlightLines<'static>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        let width = terminal::size().map_or(80, |(columns, _)| columns as usize);
+        Self {
+            width: width.max(20),
+            pending: String::new(),
+            code: None,
+            table: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, text: &str) -> Result<()> {
+        self.pending.push_str(text);
+        while let Some(end) = self.pending.find('\n') {
+            let line = self.pending.drain(..=end).collect::<String>();
+            self.line(line.trim_end_matches(['\n', '\r']))?;
+        }
+        Ok(())
+    }
+
+    /// Renders whatever is left once the answer is complete
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            let line = mem::take(&mut self.pending);
+            self.line(&line)?;
+        }
+        self.flush_table()?;
+        self.code = None;
+        Ok(())
+    }
+
+    fn line(&mut self, line: &str) -> Result<()> {
+        let trimmed = line.trim_start();
+        if let Some(code) = &mut self.code {
+            if trimmed.trim_end() == code.fence {
+                self.code = None;
+                return write_line(&trimmed.dim().to_string());
+            }
+            let line = format!("{}\n", line);
+            let ranges = code.highlighter.highlight_line(&line, syntaxes())?;
+            let highlighted = as_24_bit_terminal_escaped(&ranges, false);
+            return write_line(&format!("{}\x1b[0m", highlighted.trim_end_matches('\n')));
+        }
+
+        if trimmed.starts_with('|') {
+            self.table.push(trimmed.trim_end().to_string());
+            return Ok(());
+        }
+        self.flush_table()?;
+
+        if let Some(fence) = fence(trimmed) {
+            let language = trimmed[fence.len()..].trim();
+            let syntax = syntaxes()
+                .find_syntax_by_token(language)
+                .unwrap_or_else(|| syntaxes().find_syntax_plain_text());
+            self.code = Some(CodeBlock {
+                fence,
+                highlighter: HighlightLines::new(syntax, theme()),
+            });
+            return write_line(&trimmed.dim().to_string());
+        }
+        write_line(&self.block(line))
+    }
+
+    /// Renders a line outside code blocks and tables
+    fn block(&self, line: &str) -> String {
+        let trimmed = line.trim_start();
+        let indent = " ".repeat(line.len() - trimmed.len());
+        if trimmed.is_empty() {
+            return String::new();
+        }
+
+        let level = trimmed.chars().take_while(|c| *c == '#').count();
+        if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+            let text = inline(trimmed[level..].trim()).bold().cyan();
+            let text = if level == 1 {
+                text.underlined().to_string()
+            } else {
+                text.to_string()
+            };
+            return self.wrap(&text, "", "");
+        }
+
+        let marker = trimmed.chars().next().unwrap_or_default();
+        if matches!(marker, '-' | '*' | '_')
+            && trimmed.chars().filter(|c| *c == marker).count() >= 3
+            && trimmed.chars().all(|c| c == marker || c == ' ')
+        {
+            return "─".repeat(self.width).dim().to_string();
+        }
+
+        if let Some(quote) = trimmed.strip_prefix('>') {
+            let prefix = format!("{}{} ", indent, "│".dim());
+            return self.wrap(&inline(quote.trim()).italic().to_string(), &prefix, &prefix);
+        }
+
+        if let Some((bullet, text)) = list_item(trimmed) {
+            let first = format!("{}{} ", indent, bullet);
+            let rest = " ".repeat(indent.len() + display_width(&bullet) + 1);
+            return self.wrap(&inline(text), &first, &rest);
+        }
+
+        self.wrap(&inline(trimmed), &indent, &indent)
+    }
+
+    fn wrap(&self, text: &str, first: &str, rest: &str) -> String {
+        let options = textwrap::Options::new(self.width)
+            .initial_indent(first)
+            .subsequent_indent(rest);
+        textwrap::fill(text, options)
+    }
+
+    fn flush_table(&mut self) -> Result<()> {
+        if self.table.is_empty() {
+            return Ok(());
+        }
+        let rows = mem::take(&mut self.table)
+            .iter()
+            .map(|row| {
+                row.trim_matches('|')
+                    .split('|')
+                    .map(|cell| inline(cell.trim()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let is_separator = |row: &[String]| {
+            row.iter()
+                .all(|cell| !cell.is_empty() && cell.chars().all(|c| matches!(c, '-' | ':' | ' ')))
+        };
+
+        let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+        let mut widths = vec![0; columns];
+        for row in rows.iter().filter(|row| !is_separator(row)) {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(display_width(cell));
+            }
+        }
+
+        for (i, row) in rows.iter().enumerate() {
+            if is_separator(row) {
+                let rule = widths
+                    .iter()
+                    .map(|width| "─".repeat(*width))
+                    .collect::<Vec<_>>()
+                    .join("─┼─");
+                write_line(&rule.dim().to_string())?;
+                continue;
+            }
+            let cells = widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    let cell = row.get(column).map(String::as_str).unwrap_or_default();
+                    let padded = format!("{}{}", cell, " ".repeat(width - display_width(cell)));
+                    // A header is the row right above the separator
+                    if rows.get(i + 1).is_some_and(|next| is_separator(next)) {
+                        padded.bold().to_string()
+                    } else {
+                        padded
+                    }
+                })
+                .collect::<Vec<_>>();
+            write_line(&cells.join(&" │ ".dim().to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+fn write_line(text: &str) -> Result<()> {
+    let mut out = stdout().lock();
+    writeln!(out, "{}", text)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// The opening fence of a code block, three or more backticks or tildes
+pub fn fence(line: &str) -> Option<String> {
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence = line
+        .chars()
+        .take_while(|c| *c == marker)
+        .collect::<String>();
+    (fence.len() >= 3).then_some(fence)
+}
+
+/// Splits a list item into the bullet to show and its text
+fn list_item(line: &str) -> Option<(String, &str)> {
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(text) = line.strip_prefix(marker) {
+            return Some(("•".to_string(), text));
+        }
+    }
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let rest = &line[digits..];
+    if digits > 0 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return Some((line[..digits + 1].to_string(), &rest[2..]));
+    }
+    None
+}
+
+/// Styles inline code, bold, italics and links
+fn inline(text: &str) -> String {
+    let mut output = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            if let Some(end) = rest[1..].find('`') {
+                output.push_str(&rest[1..=end].yellow().to_string());
+                rest = &rest[end + 2..];
+                continue;
+            }
+        } else if rest.starts_with("**") || rest.starts_with("__") {
+            let marker = &rest[..2];
+            if let Some(end) = rest[2..].find(marker).filter(|end| *end > 0) {
+                output.push_str(&inline(&rest[2..2 + end]).bold().to_string());
+                rest = &rest[end + 4..];
+                continue;
+            }
+        } else if c == '*' && !rest[1..].starts_with(' ') {
+            if let Some(end) = rest[1..].find('*').filter(|end| *end > 0) {
+                output.push_str(&inline(&rest[1..=end]).italic().to_string());
+                rest = &rest[end + 2..];
+                continue;
+            }
+        } else if c == '[' {
+            if let Some(close) = rest.find("](") {
+                if let Some(end) = rest[close..].find(')') {
+                    let label = &rest[1..close];
+                    let url = &rest[close + 2..close + end];
+                    output.push_str(&label.underlined().to_string());
+                    output.push_str(&format!(" ({})", url).dim().to_string());
+                    rest = &rest[close + end + 1..];
+                    continue;
+                }
+            }
+        }
+        output.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    output
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme() -> &'static Theme {
+    &THEMES.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_fences() {
+        assert_eq!(fence("```rust").as_deref(), Some("```"));
+        assert_eq!(fence("~~~~").as_deref(), Some("~~~~"));
+        assert_eq!(fence("``not a fence"), None);
+        assert_eq!(fence("text ```"), None);
+    }
+
+    #[test]
+    fn closes_an_unclosed_fence_when_finished() {
+        let mut renderer = Renderer::new();
+        renderer.push("```rust\nfn main() {}\n").unwrap();
+        assert!(renderer.code.is_some());
+        renderer.finish().unwrap();
+        assert!(renderer.code.is_none());
+    }
+
+    #[test]
+    fn splits_list_items() {
+        assert_eq!(list_item("- milk"), Some(("•".to_string(), "milk")));
+        assert_eq!(list_item("* eggs"), Some(("•".to_string(), "eggs")));
+        assert_eq!(list_item("12. flour"), Some(("12.".to_string(), "flour")));
+        assert_eq!(list_item("3) sugar"), Some(("3)".to_string(), "sugar")));
+        assert_eq!(list_item("1.5 cups"), None);
+        assert_eq!(list_item("-dash"), None);
+    }
+
+    #[test]
+    fn styles_inline_markup() {
+        assert_eq!(inline("run `ls`"), format!("run {}", "ls".yellow()));
+        assert_eq!(
+            inline("see [docs](https://example.com)"),
+            format!(
+                "see {}{}",
+                "docs".underlined(),
+                " (https://example.com)".dim()
+            )
+        );
+    }
+
+    #[test]
+    fn nests_emphasis() {
+        assert_eq!(
+            inline("**very *important* note**"),
+            format!("very {} note", "important".italic())
+                .bold()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn leaves_unmatched_markers_alone() {
+        assert_eq!(inline("a `b"), "a `b");
+        assert_eq!(inline("* not italic"), "* not italic");
+        assert_eq!(inline("2 ** 3"), "2 ** 3");
+        assert_eq!(inline("[label](no close"), "[label](no close");
+    }
+}
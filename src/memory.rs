@@ -1,27 +1,142 @@
-use anyhow::{Context, Result};
-use std::fs;
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{stderr, stdin, Write};
 use std::path::PathBuf;
 
+use crate::crypto::Vault;
+use crate::editor;
+use crate::paths;
+use crate::settings::Settings;
+use crate::storage;
+use crate::sync::MemorySync;
+
 pub struct Memory;
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MemoryEntry {
+    pub id: String,
+    pub content: String,
+}
+
+impl MemoryEntry {
+    pub fn new(content: &str) -> Self {
+        Self {
+            id: storage::fingerprint(content),
+            content: content.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum MemoryFormat {
+    Json,
+    Markdown,
+}
+
+#[derive(Serialize, Deserialize)]
+struct MemoryExport {
+    exported: String,
+    entries: Vec<MemoryEntry>,
+}
+
 impl Memory {
     pub fn load() -> Result<String> {
         let path = Self::memory_path()?;
-        if path.exists() {
-            Ok(fs::read_to_string(path)?)
-        } else {
-            Ok(String::new())
-        }
+        Ok(storage::read(&path)?.unwrap_or_default())
+    }
+
+    /// Returns the individual entries, which are separated by blank lines
+    pub fn entries() -> Result<Vec<MemoryEntry>> {
+        Ok(Self::parse(&Self::load()?))
+    }
+
+    pub fn parse(content: &str) -> Vec<MemoryEntry> {
+        content
+            .split("\n\n")
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(MemoryEntry::new)
+            .collect()
     }
 
     pub fn save(content: &str) -> Result<()> {
+        let _lock = storage::lock()?;
         let path = Self::memory_path()?;
-        fs::create_dir_all(path.parent().unwrap())?;
-        fs::write(path, content)?;
+        storage::write(&path, content)?;
+        if Settings::load()?.memory_sync {
+            MemorySync::commit("Update memory")?;
+        }
         Ok(())
     }
 
+    pub fn save_entries(entries: &[MemoryEntry]) -> Result<()> {
+        Self::save(&Self::join(entries))
+    }
+
+    pub fn join(entries: &[MemoryEntry]) -> String {
+        entries
+            .iter()
+            .map(|entry| entry.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    pub fn export(format: MemoryFormat) -> Result<String> {
+        let entries = Self::entries()?;
+        match format {
+            MemoryFormat::Json => Ok(serde_json::to_string_pretty(&MemoryExport {
+                exported: Local::now().to_rfc3339(),
+                entries,
+            })?),
+            MemoryFormat::Markdown => {
+                let mut output = String::from("# Terminus memory\n");
+                for entry in entries {
+                    output.push_str(&format!("\n## {}\n\n{}\n", entry.id, entry.content));
+                }
+                Ok(output)
+            }
+        }
+    }
+
+    /// Adds entries from an export that aren't already stored, returning how many were added
+    pub fn import(contents: &str, format: MemoryFormat) -> Result<usize> {
+        let imported = match format {
+            MemoryFormat::Json => {
+                let export: MemoryExport =
+                    serde_json::from_str(contents).context("Invalid memory export")?;
+                export
+                    .entries
+                    .iter()
+                    .flat_map(|entry| Self::parse(&entry.content))
+                    .collect::<Vec<_>>()
+            }
+            MemoryFormat::Markdown => contents
+                .split("\n## ")
+                .skip(1)
+                .filter_map(|section| section.split_once('\n'))
+                .flat_map(|(_, content)| Self::parse(content))
+                .collect(),
+        };
+
+        let _lock = storage::lock()?;
+        let mut entries = Self::entries()?;
+        let mut added = 0;
+        for entry in imported {
+            if !entries.iter().any(|existing| existing.id == entry.id) {
+                entries.push(entry);
+                added += 1;
+            }
+        }
+        if added > 0 {
+            Self::save_entries(&entries)?;
+        }
+        Ok(added)
+    }
+
     pub fn append(content: &str) -> Result<()> {
+        let _lock = storage::lock()?;
         let mut current = Self::load()?;
         if !current.is_empty() {
             current.push_str("\n\n");
@@ -30,19 +145,91 @@ impl Memory {
         Self::save(&current)
     }
 
-    pub fn edit() -> Result<()> {
-        let path = Self::memory_path()?;
-        if !path.exists() {
-            Self::save("")?;
+    /// Edits memory in `$VISUAL`/`$EDITOR`, re-opening the editor until the
+    /// text is valid; returns false if memory was left unchanged. Refused
+    /// while the store is encrypted, since the editor works on a plaintext
+    /// temporary copy
+    pub fn edit() -> Result<bool> {
+        if Vault::is_enabled()? {
+            bail!(
+                "Memory can't be edited while the store is encrypted, because the editor \
+                 would see it in a plaintext temporary file; run `terminus store decrypt` \
+                 first, or use `terminus memory add`"
+            );
+        }
+        let original = Self::load()?;
+        let mut text = original.clone();
+        loop {
+            text = editor::compose(&text, ".txt")?;
+            let Err(error) = Self::check(&text) else {
+                break;
+            };
+            eprintln!("Invalid memory: {:#}", error);
+            eprint!("Re-open the editor? [Y/n]: ");
+            stderr().flush()?;
+            let mut answer = String::new();
+            stdin().read_line(&mut answer)?;
+            if answer.trim().eq_ignore_ascii_case("n") {
+                bail!("Memory left unchanged");
+            }
+        }
+
+        let _lock = storage::lock()?;
+        if Self::load()? != original {
+            bail!("Memory changed while it was being edited; run the edit again");
+        }
+        if text.trim() == original.trim() {
+            return Ok(false);
+        }
+        Self::save(text.trim())?;
+        Ok(true)
+    }
+
+    /// Rejects text that wouldn't read back as the entries it shows: lines of
+    /// only spaces look like entry separators but aren't, and text starting
+    /// like an encrypted file would be taken for one
+    fn check(text: &str) -> Result<()> {
+        if let Some(number) = text
+            .lines()
+            .position(|line| !line.is_empty() && line.trim().is_empty())
+        {
+            bail!(
+                "line {} has only spaces; separate entries with empty lines",
+                number + 1
+            );
+        }
+        if Vault::is_encrypted(text.trim().as_bytes()) {
+            bail!("memory can't start with the encrypted file header");
         }
-        open::that(path).context("Failed to open memory file in editor")?;
         Ok(())
     }
 
     fn memory_path() -> Result<PathBuf> {
-        let mut path = dirs::config_dir().expect("Failed to get config directory");
-        path.push("terminus");
+        let mut path = paths::home()?;
         path.push("memory.txt");
         Ok(path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_entries_separated_by_empty_lines() {
+        assert!(Memory::check("Prefers Rust\n\nUses fish\nand vim\n").is_ok());
+        assert!(Memory::check("").is_ok());
+    }
+
+    #[test]
+    fn rejects_separators_with_spaces() {
+        let error = Memory::check("Prefers Rust\n  \nUses fish").unwrap_err();
+        assert!(error.to_string().starts_with("line 2"));
+        assert_eq!(Memory::parse("Prefers Rust\n  \nUses fish").len(), 1);
+    }
+
+    #[test]
+    fn rejects_the_encrypted_header() {
+        assert!(Memory::check("TERMINUS-ENC1\nnot really").is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io::{stdin, stdout, IsTerminal};
+
+use crate::llm::Usage;
+use crate::session::{ContextRecord, Exchange};
+
+/// How one-shot answers are written to stdout
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// The answer as plain text
+    #[default]
+    Text,
+    /// A pretty-printed JSON object with the answer and metadata
+    Json,
+    /// One compact JSON object per line
+    Jsonl,
+    /// The answer followed by a metadata footer
+    Markdown,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    answer: &'a str,
+    model: &'a str,
+    finish_reason: Option<&'a str>,
+    usage: Option<&'a Usage>,
+    latency_ms: u64,
+    session_id: &'a str,
+    timestamp: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    context: Option<&'a ContextRecord>,
+}
+
+/// Whether answers are rendered as styled Markdown while they stream in,
+/// which only happens for text output to a terminal
+pub fn renders(format: OutputFormat, raw: bool) -> bool {
+    format == OutputFormat::Text && !raw && stdout().is_terminal()
+}
+
+/// Writes an answer to stdout, unless it was already rendered while
+/// streaming; context and other diagnostics go to stderr unless they are part
+/// of a JSON record
+pub fn print_exchange(
+    exchange: &Exchange,
+    session_id: &str,
+    format: OutputFormat,
+    show_context: bool,
+    rendered: bool,
+) -> Result<()> {
+    let record = Record {
+        answer: &exchange.response,
+        model: &exchange.model,
+        finish_reason: exchange.finish_reason.as_deref(),
+        usage: exchange.usage.as_ref(),
+        latency_ms: exchange.latency_ms,
+        session_id,
+        timestamp: &exchange.timestamp,
+        context: show_context.then_some(&exchange.context),
+    };
+    match format {
+        OutputFormat::Text if rendered => {}
+        OutputFormat::Text => {
+            // Label the answer only for a person at a terminal
+            if stdin().is_terminal() && stdout().is_terminal() {
+                println!("Response: {}", exchange.response);
+            } else {
+                println!("{}", exchange.response);
+            }
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&record)?),
+        OutputFormat::Jsonl => println!("{}", serde_json::to_string(&record)?),
+        OutputFormat::Markdown => {
+            println!("{}\n", exchange.response.trim_end());
+            let mut footer = vec![
+                format!("model: {}", exchange.model),
+                format!("latency: {} ms", exchange.latency_ms),
+                format!("session: {}", session_id),
+            ];
+            if let Some(usage) = &exchange.usage {
+                footer.insert(1, format!("tokens: {}", usage.total_tokens));
+            }
+            println!("---\n_{}_", footer.join(" · "));
+        }
+    }
+    if show_context && matches!(format, OutputFormat::Text | OutputFormat::Markdown) {
+        exchange.context.eprint();
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::{self, PathBuf};
+use std::sync::OnceLock;
+
+// Directory next to the executable that holds data in portable mode
+const PORTABLE_DIR: &str = "terminus-data";
+
+// Home given on the command line, set once at startup
+static HOME_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn set_home(path: PathBuf) -> Result<()> {
+    let path = path::absolute(&path)
+        .with_context(|| format!("Invalid home directory: {}", path.display()))?;
+    let _ = HOME_OVERRIDE.set(path);
+    Ok(())
+}
+
+/// Keeps data in a directory next to the executable from now on
+pub fn enable_portable() -> Result<PathBuf> {
+    let dir = portable_dir()?;
+    fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create portable data directory {}", dir.display()))?;
+    Ok(dir)
+}
+
+/// The directory Terminus keeps its data in: `--home`, then `TERMINUS_HOME`,
+/// then a portable data directory next to the executable, then the platform
+/// config directory
+pub fn home() -> Result<PathBuf> {
+    if let Some(path) = HOME_OVERRIDE.get() {
+        return Ok(path.clone());
+    }
+    if let Some(path) = env::var_os("TERMINUS_HOME").filter(|path| !path.is_empty()) {
+        return path::absolute(PathBuf::from(path)).context("Invalid TERMINUS_HOME");
+    }
+    if let Ok(dir) = exe_dir() {
+        let portable = dir.join(PORTABLE_DIR);
+        if portable.is_dir() {
+            return Ok(portable);
+        }
+    }
+    match dirs::config_dir() {
+        Some(mut path) => {
+            path.push("terminus");
+            Ok(path)
+        }
+        None => {
+            bail!("Could not determine a config directory; set TERMINUS_HOME or pass --home <DIR>")
+        }
+    }
+}
+
+/// Checks that a persona, template or similar name is safe to use as a file
+/// name inside the store, so it can't point outside its directory
+pub fn validate_name(kind: &str, name: &str) -> Result<()> {
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "{} names may only contain letters, digits, '-' and '_'",
+            kind
+        );
+    }
+    Ok(())
+}
+
+/// The portable data directory next to the executable, whether or not it exists
+pub fn portable_dir() -> Result<PathBuf> {
+    Ok(exe_dir()?.join(PORTABLE_DIR))
+}
+
+fn exe_dir() -> Result<PathBuf> {
+    let exe = env::current_exe().context("Could not locate the terminus executable")?;
+    match exe.parent() {
+        Some(dir) => Ok(dir.to_path_buf()),
+        None => bail!("Could not locate the terminus executable's directory"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_names() {
+        for name in ["reviewer", "code-review", "v2_draft", "A1"] {
+            assert!(validate_name("Persona", name).is_ok(), "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_paths() {
+        for name in [
+            "",
+            "..",
+            "../settings",
+            "a/b",
+            "a\\b",
+            "/etc/passwd",
+            "name.json",
+            "a b",
+        ] {
+            let error = validate_name("Template", name).unwrap_err();
+            assert!(error.to_string().starts_with("Template names"), "{name}");
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::history::History;
+use crate::paths;
+use crate::settings::Settings;
+use crate::storage;
+use crate::template;
+
+/// A named system message with its own model and sampling defaults
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Persona {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub system_message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+}
+
+impl Persona {
+    pub fn new(name: &str) -> Result<Self> {
+        paths::validate_name("Persona", name)?;
+        Ok(Self {
+            name: name.to_string(),
+            description: String::new(),
+            system_message: String::new(),
+            model: None,
+            temperature: None,
+            top_p: None,
+        })
+    }
+
+    pub fn load(name: &str) -> Result<Self> {
+        paths::validate_name("Persona", name)?;
+        match storage::read(&Self::persona_path(name)?)? {
+            Some(contents) => Ok(serde_json::from_str(&contents)?),
+            None => bail!("Persona not found: {}", name),
+        }
+    }
+
+    pub fn exists(name: &str) -> Result<bool> {
+        paths::validate_name("Persona", name)?;
+        Ok(Self::persona_path(name)?.exists())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        paths::validate_name("Persona", &self.name)?;
+        template::validate(&self.system_message)?;
+        if let Some(temperature) = self.temperature {
+            if !(0.0..=2.0).contains(&temperature) {
+                bail!("Temperature must be between 0 and 2");
+            }
+        }
+        if let Some(top_p) = self.top_p {
+            if !(0.0..=1.0).contains(&top_p) {
+                bail!("top_p must be between 0 and 1");
+            }
+        }
+        let _lock = storage::lock()?;
+        let path = Self::persona_path(&self.name)?;
+        let previous = match storage::read(&path)? {
+            Some(contents) => Some(serde_json::from_str::<Self>(&contents)?),
+            None => None,
+        };
+        let contents = serde_json::to_string_pretty(self)?;
+        storage::write(&path, &contents)?;
+
+        let previous_contents = match &previous {
+            Some(previous) => Some(serde_json::to_string_pretty(previous)?),
+            None => None,
+        };
+        History::persona(&self.name)?.record(
+            previous_contents
+                .as_deref()
+                .zip(previous.as_ref().map(|p| p.system_message.as_str())),
+            &contents,
+            &self.system_message,
+        )
+    }
+
+    pub fn delete(name: &str) -> Result<()> {
+        paths::validate_name("Persona", name)?;
+        let path = Self::persona_path(name)?;
+        if !path.exists() {
+            bail!("Persona not found: {}", name);
+        }
+        fs::remove_file(path)?;
+
+        if Settings::load_global()?.persona.as_deref() == Some(name) {
+            Settings::update(|settings| settings.persona = None)?;
+        }
+        Ok(())
+    }
+
+    pub fn list() -> Result<Vec<Self>> {
+        Self::names()?.iter().map(|name| Self::load(name)).collect()
+    }
+
+    pub fn names() -> Result<Vec<String>> {
+        storage::names(&Self::personas_dir()?, "json")
+    }
+
+    /// The persona selected in settings, if any
+    pub fn active() -> Result<Option<Self>> {
+        match Settings::load()?.persona {
+            Some(name) => Ok(Some(Self::load(&name)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Name: {}", self.name);
+        if !self.description.is_empty() {
+            println!("Description: {}", self.description);
+        }
+        if let Some(model) = &self.model {
+            println!("Model: {}", model);
+        }
+        if let Some(temperature) = self.temperature {
+            println!("Temperature: {}", temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            println!("Top p: {}", top_p);
+        }
+        println!("System message:\n{}", self.system_message);
+    }
+
+    fn persona_path(name: &str) -> Result<PathBuf> {
+        let mut path = Self::personas_dir()?;
+        path.push(format!("{}.json", name));
+        Ok(path)
+    }
+
+    fn personas_dir() -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push("personas");
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delete_rejects_paths() {
+        for name in ["../settings", "a/b", "..", ""] {
+            assert!(Persona::delete(name).is_err(), "{name}");
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{stderr, stdin, Write};
+use std::path::PathBuf;
+
+use crate::paths;
+use crate::storage;
+use crate::template;
+
+/// A reusable prompt with named parameters
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PromptTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub prompt: String,
+    #[serde(default)]
+    pub params: Vec<TemplateParam>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateParam {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+impl PromptTemplate {
+    pub fn load(name: &str) -> Result<Self> {
+        paths::validate_name("Template", name)?;
+        match storage::read(&Self::template_path(name)?)? {
+            Some(contents) => Ok(serde_json::from_str(&contents)?),
+            None => bail!("Template not found: {}", name),
+        }
+    }
+
+    pub fn exists(name: &str) -> Result<bool> {
+        paths::validate_name("Template", name)?;
+        Ok(Self::template_path(name)?.exists())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        paths::validate_name("Template", &self.name)?;
+        template::validate(&self.prompt)?;
+        let path = Self::template_path(&self.name)?;
+        storage::write(&path, &serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn delete(name: &str) -> Result<()> {
+        paths::validate_name("Template", name)?;
+        let path = Self::template_path(name)?;
+        if !path.exists() {
+            bail!("Template not found: {}", name);
+        }
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    pub fn list() -> Result<Vec<Self>> {
+        Self::names()?.iter().map(|name| Self::load(name)).collect()
+    }
+
+    pub fn names() -> Result<Vec<String>> {
+        storage::names(&Self::templates_dir()?, "json")
+    }
+
+    /// Fills in the prompt from `vars`, then defaults, then runtime variables,
+    /// asking for anything still missing
+    pub fn render(&self, vars: &HashMap<String, String>) -> Result<String> {
+        let mut values = HashMap::new();
+        for name in template::placeholders(&self.prompt)? {
+            let param = self.params.iter().find(|param| param.name == name);
+            let value = match vars.get(&name) {
+                Some(value) => value.clone(),
+                None => match param.and_then(|param| param.default.clone()) {
+                    Some(default) => default,
+                    None => match template::runtime_variable(&name) {
+                        Some(value) if param.is_none() => value,
+                        _ => Self::ask_value(&name, param)?,
+                    },
+                },
+            };
+            values.insert(name, value);
+        }
+        template::render(&self.prompt, &|name| values.get(name).cloned())
+    }
+
+    pub fn print(&self) {
+        println!("Name: {}", self.name);
+        if !self.description.is_empty() {
+            println!("Description: {}", self.description);
+        }
+        if let Some(persona) = &self.persona {
+            println!("Persona: {}", persona);
+        }
+        if let Some(model) = &self.model {
+            println!("Model: {}", model);
+        }
+        if !self.params.is_empty() {
+            println!("Parameters:");
+            for param in &self.params {
+                let mut line = format!("  {}", param.name);
+                if let Some(default) = &param.default {
+                    line.push_str(&format!(" (default: {})", default));
+                }
+                if !param.description.is_empty() {
+                    line.push_str(&format!(" - {}", param.description));
+                }
+                println!("{}", line);
+            }
+        }
+        println!("Prompt:\n{}", self.prompt);
+    }
+
+    fn ask_value(name: &str, param: Option<&TemplateParam>) -> Result<String> {
+        match param.map(|param| param.description.as_str()) {
+            Some(description) if !description.is_empty() => {
+                eprint!("{} ({}): ", name, description)
+            }
+            _ => eprint!("{}: ", name),
+        }
+        stderr().flush()?;
+        let mut value = String::new();
+        stdin().read_line(&mut value)?;
+        Ok(value.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    fn template_path(name: &str) -> Result<PathBuf> {
+        let mut path = Self::templates_dir()?;
+        path.push(format!("{}.json", name));
+        Ok(path)
+    }
+
+    fn templates_dir() -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push("templates");
+        Ok(path)
+    }
+}
+
+/// Parses `key=value` pairs given with `--var`
+pub fn parse_vars(vars: &[String]) -> Result<HashMap<String, String>> {
+    vars.iter()
+        .map(|var| match var.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.to_string())),
+            None => bail!("Invalid --var '{}', expected key=value", var),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delete_rejects_paths() {
+        for name in ["../settings", "a/b", "..", ""] {
+            assert!(PromptTemplate::delete(name).is_err(), "{name}");
+        }
+    }
+}
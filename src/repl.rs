@@ -0,0 +1,523 @@
+use anyhow::{anyhow, bail, Result};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Context, Editor, Helper};
+use std::fs;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::codeblock::CodeBlock;
+use crate::markdown::Renderer;
+use crate::memory::Memory;
+use crate::persona::Persona;
+use crate::prompttemplate::{self, PromptTemplate};
+use crate::session::{Exchange, Session};
+use crate::settings::Settings;
+use crate::systemmessage::SystemMessage;
+use crate::{editor, paths, storage};
+
+const COMMANDS: &[(&str, &str)] = &[
+    (
+        "/edit",
+        "Write a prompt in $EDITOR, starting from the last one",
+    ),
+    ("/model", "[NAME]  Show models, or switch the default model"),
+    (
+        "/persona",
+        "[NAME|none|new NAME|edit NAME|delete NAME]  Show, switch or manage personas",
+    ),
+    (
+        "/memory",
+        "[add TEXT|on|off|edit]  Show memory, add an entry, or turn it on or off",
+    ),
+    ("/system", "[set TEXT]  Show or replace the system message"),
+    (
+        "/template",
+        "NAME [KEY=VALUE...]  Send a prompt template in this conversation",
+    ),
+    (
+        "/code",
+        "[N|save N PATH|append N PATH|run N]  List or use the last response's code blocks",
+    ),
+    ("/context", "Show what was sent with the last response"),
+    ("/save", "[PATH]  Write the conversation to a Markdown file"),
+    ("/clear", "Forget the conversation and start a new session"),
+    ("/help", "Show this help"),
+    ("/exit", "Leave the chat (or press Ctrl-D)"),
+];
+
+// Saved prompts kept for reverse search across chats
+const HISTORY_LIMIT: usize = 1000;
+
+/// Line editor support: slash command completion and multi-line input
+struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let word = &line[..pos];
+        if !word.starts_with('/') || word.contains(' ') {
+            return Ok((pos, Vec::new()));
+        }
+        let candidates = COMMANDS
+            .iter()
+            .filter(|(name, _)| name.starts_with(word))
+            .map(|(name, _)| Pair {
+                display: name.to_string(),
+                replacement: format!("{} ", name),
+            })
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Validator for ReplHelper {
+    /// A trailing `\` or an unclosed `"""` block continues on the next line
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        let input = ctx.input();
+        if input.ends_with('\\') || input.matches(r#"""""#).count() % 2 == 1 {
+            Ok(ValidationResult::Incomplete)
+        } else {
+            Ok(ValidationResult::Valid(None))
+        }
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Helper for ReplHelper {}
+
+enum Flow {
+    Continue,
+    Exit,
+}
+
+struct Chat {
+    settings: Settings,
+    persona_override: Option<Persona>,
+    session: Session,
+    // Most recent prompt, kept even if sending it failed so /edit can retry it
+    last_prompt: String,
+    raw: bool,
+}
+
+/// Runs the chat until `/exit` or end of input
+pub async fn run(settings: Settings, persona_override: Option<Persona>, raw: bool) -> Result<()> {
+    let mut editor = Editor::<ReplHelper, DefaultHistory>::new()?;
+    editor.set_helper(Some(ReplHelper));
+    for entry in load_history()? {
+        editor.add_history_entry(entry)?;
+    }
+
+    println!("Type a prompt, or /help for commands. End a line with \\ to continue it.");
+    let mut chat = Chat {
+        settings,
+        persona_override,
+        session: Session::new(),
+        last_prompt: String::new(),
+        raw,
+    };
+    loop {
+        let line = match editor.readline(&chat.prompt()?) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error.into()),
+        };
+        let input = join_lines(&line);
+        if input.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str())?;
+        save_history(&editor)?;
+
+        let result = match input.strip_prefix('/') {
+            Some(command) => chat.command(command).await,
+            None => chat.ask(&input).await.map(|_| Flow::Continue),
+        };
+        match result {
+            Ok(Flow::Exit) => break,
+            Ok(Flow::Continue) => {}
+            Err(error) => eprintln!("Error: {:#}", error),
+        }
+    }
+    Ok(())
+}
+
+impl Chat {
+    fn prompt(&self) -> Result<String> {
+        Ok(match self.persona()? {
+            Some(persona) => format!("{}> ", persona.name),
+            None => "terminus> ".to_string(),
+        })
+    }
+
+    fn persona(&self) -> Result<Option<Persona>> {
+        match &self.persona_override {
+            Some(persona) => Ok(Some(persona.clone())),
+            None => Persona::active(),
+        }
+    }
+
+    async fn ask(&mut self, prompt: &str) -> Result<()> {
+        self.last_prompt = prompt.to_string();
+        let persona = self.persona()?;
+        let mut renderer = self.renderer();
+        let exchange = crate::send_prompt(
+            prompt,
+            &self.settings,
+            persona.as_ref(),
+            None,
+            &mut self.session,
+            renderer.as_mut(),
+        )
+        .await?;
+        self.print(&exchange, renderer.is_some());
+        Ok(())
+    }
+
+    fn renderer(&self) -> Option<Renderer> {
+        (!self.raw && stdout().is_terminal()).then(Renderer::new)
+    }
+
+    fn print(&self, exchange: &Exchange, rendered: bool) {
+        if rendered {
+            println!();
+        } else {
+            println!("{}\n", exchange.response.trim_end());
+        }
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Flow> {
+        let (name, rest) = command.split_once(' ').unwrap_or((command, ""));
+        let rest = rest.trim();
+        match name {
+            "edit" => {
+                let prompt = editor::compose(&self.last_prompt, ".md")?;
+                let prompt = prompt.trim();
+                if prompt.is_empty() {
+                    println!("Nothing to send");
+                } else {
+                    self.ask(prompt).await?;
+                }
+            }
+            "model" => self.model(rest)?,
+            "persona" => match rest.split_once(' ') {
+                Some(("new", name)) => self.edit_persona(name.trim(), true)?,
+                Some(("edit", name)) => self.edit_persona(name.trim(), false)?,
+                Some(("delete", name)) => self.delete_persona(name.trim())?,
+                _ => self.switch_persona(rest)?,
+            },
+            "memory" => self.memory(rest)?,
+            "system" => match rest.split_once(' ') {
+                Some(("set", message)) => {
+                    SystemMessage::save(message.trim())?;
+                    println!("System message updated successfully");
+                }
+                _ if rest.is_empty() => {
+                    println!("Current system message:\n{}", SystemMessage::load_raw()?)
+                }
+                _ => bail!("Usage: /system [set TEXT]"),
+            },
+            "template" => {
+                let mut words = rest.split_whitespace();
+                let Some(name) = words.next() else {
+                    for template in PromptTemplate::list()? {
+                        println!("  {} - {}", template.name, template.description);
+                    }
+                    return Ok(Flow::Continue);
+                };
+                let template = PromptTemplate::load(name)?;
+                let vars =
+                    prompttemplate::parse_vars(&words.map(str::to_string).collect::<Vec<_>>())?;
+                let mut renderer = self.renderer();
+                let exchange = crate::run_template(
+                    &template,
+                    &vars,
+                    &self.settings,
+                    self.persona_override.as_ref(),
+                    &mut self.session,
+                    renderer.as_mut(),
+                )
+                .await?;
+                self.print(&exchange, renderer.is_some());
+            }
+            "code" => self.code(rest).await?,
+            "context" => match self.session.last_exchange() {
+                Some(exchange) => exchange.context.print(),
+                None => println!("No response in this conversation yet"),
+            },
+            "save" => {
+                let path = if rest.is_empty() {
+                    PathBuf::from(format!("terminus-{}.md", self.session.id))
+                } else {
+                    PathBuf::from(rest)
+                };
+                fs::write(&path, self.transcript())?;
+                println!("Conversation saved to {}", path.display());
+            }
+            "clear" => {
+                self.session = Session::new();
+                println!("Conversation cleared");
+            }
+            "help" => {
+                for (name, help) in COMMANDS {
+                    println!("  {:<10} {}", name, help);
+                }
+            }
+            "exit" | "quit" => return Ok(Flow::Exit),
+            _ => bail!("Unknown command /{}; type /help for a list", name),
+        }
+        Ok(Flow::Continue)
+    }
+
+    fn model(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            for model in crate::AVAILABLE_MODELS {
+                let marker = if self.settings.model == *model {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}", marker, model);
+            }
+            return Ok(());
+        }
+        if !crate::AVAILABLE_MODELS.contains(&name) {
+            println!(
+                "Warning: {} is not a known model ({})",
+                name,
+                crate::AVAILABLE_MODELS.join(", ")
+            );
+        }
+        Settings::update(|global| global.model = name.to_string())?;
+        self.settings.model = name.to_string();
+        println!("Model set to: {}", name);
+        Ok(())
+    }
+
+    fn switch_persona(&mut self, name: &str) -> Result<()> {
+        if name.is_empty() {
+            let active = self.persona()?.map(|persona| persona.name);
+            for persona in Persona::list()? {
+                let marker = if active.as_deref() == Some(persona.name.as_str()) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {} - {}", marker, persona.name, persona.description);
+            }
+            return Ok(());
+        }
+        let name = (name != "none").then(|| name.to_string());
+        crate::use_persona(name)?;
+        self.persona_override = None;
+        self.settings.persona = Settings::load()?.persona;
+        Ok(())
+    }
+
+    /// Creates or edits a persona by asking for each field in turn
+    fn edit_persona(&mut self, name: &str, create: bool) -> Result<()> {
+        let mut persona = if create {
+            if Persona::exists(name)? {
+                bail!("Persona already exists: {}", name);
+            }
+            Persona::new(name)?
+        } else {
+            Persona::load(name)?
+        };
+        println!("Leave a field empty to keep its current value");
+        let description = read_field("Description", &persona.description)?;
+        if !description.is_empty() {
+            persona.description = description;
+        }
+        let system_message = read_field("System message", &persona.system_message)?;
+        if !system_message.is_empty() {
+            persona.system_message = system_message;
+        }
+        let model = read_field("Model", persona.model.as_deref().unwrap_or_default())?;
+        if !model.is_empty() {
+            persona.model = Some(model);
+        }
+        let temperature = read_field(
+            "Temperature (0-2)",
+            &persona
+                .temperature
+                .map(|t| t.to_string())
+                .unwrap_or_default(),
+        )?;
+        if !temperature.is_empty() {
+            persona.temperature = Some(temperature.parse()?);
+        }
+        let top_p = read_field(
+            "Top p (0-1)",
+            &persona.top_p.map(|p| p.to_string()).unwrap_or_default(),
+        )?;
+        if !top_p.is_empty() {
+            persona.top_p = Some(top_p.parse()?);
+        }
+        persona.save()?;
+
+        if self
+            .persona_override
+            .as_ref()
+            .is_some_and(|active| active.name == persona.name)
+        {
+            self.persona_override = Some(persona.clone());
+        }
+        if create {
+            println!("Persona created: {}", persona.name);
+        } else {
+            println!("Persona updated: {}", persona.name);
+        }
+        Ok(())
+    }
+
+    fn delete_persona(&mut self, name: &str) -> Result<()> {
+        Persona::delete(name)?;
+        if self
+            .persona_override
+            .as_ref()
+            .is_some_and(|active| active.name == name)
+        {
+            self.persona_override = None;
+        }
+        self.settings.persona = Settings::load()?.persona;
+        println!("Persona deleted: {}", name);
+        Ok(())
+    }
+
+    fn memory(&mut self, args: &str) -> Result<()> {
+        let (action, text) = args.split_once(' ').unwrap_or((args, ""));
+        match action {
+            "" => println!("Current memory:\n{}", Memory::load()?),
+            "add" if !text.trim().is_empty() => {
+                Memory::append(text.trim())?;
+                println!("Memory entry added successfully");
+            }
+            "on" | "off" => {
+                self.settings.use_memory = action == "on";
+                Settings::update(|global| global.use_memory = action == "on")?;
+                println!(
+                    "Memory usage: {}",
+                    if action == "on" {
+                        "enabled"
+                    } else {
+                        "disabled"
+                    }
+                );
+            }
+            "edit" => crate::edit_memory()?,
+            _ => bail!("Usage: /memory [add TEXT|on|off|edit]"),
+        }
+        Ok(())
+    }
+
+    async fn code(&mut self, args: &str) -> Result<()> {
+        let Some(exchange) = self.session.last_exchange() else {
+            bail!("No response in this conversation yet");
+        };
+        let blocks = CodeBlock::extract(&exchange.response);
+        let words = args.split_whitespace().collect::<Vec<_>>();
+        let number = |word: &str| {
+            word.parse::<usize>()
+                .map_err(|_| anyhow!("Expected a block number, got {}", word))
+        };
+        match words.as_slice() {
+            [] => CodeBlock::print_list(&blocks),
+            [n] => print!("{}", CodeBlock::select(&blocks, number(n)?)?.code),
+            ["save", n, path] => {
+                CodeBlock::select(&blocks, number(n)?)?.save(Path::new(path))?;
+                println!("Code block {} saved to {}", n, path);
+            }
+            ["append", n, path] => {
+                CodeBlock::select(&blocks, number(n)?)?.append(Path::new(path))?;
+                println!("Code block {} appended to {}", n, path);
+            }
+            ["run", n] => {
+                let n = number(n)?;
+                let Some(output) = CodeBlock::select(&blocks, n)?.run()? else {
+                    return Ok(());
+                };
+                output.print();
+                if let Some(prompt) = output.follow_up(n)? {
+                    self.ask(&prompt).await?;
+                }
+            }
+            _ => bail!("Usage: /code [N|save N PATH|append N PATH|run N]"),
+        }
+        Ok(())
+    }
+
+    fn transcript(&self) -> String {
+        let mut output = format!("# Terminus session {}\n", self.session.id);
+        for exchange in &self.session.exchanges {
+            output.push_str(&format!(
+                "\n## You\n\n{}\n\n## {}\n\n{}\n",
+                exchange.prompt.trim_end(),
+                exchange.model,
+                exchange.response.trim_end()
+            ));
+        }
+        output
+    }
+}
+
+/// Asks for one persona field, showing its current value
+fn read_field(label: &str, current: &str) -> Result<String> {
+    if current.is_empty() {
+        print!("{}: ", label);
+    } else {
+        print!("{} [{}]: ", label, current);
+    }
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// Turns continuation backslashes and `"""` fences into plain newlines
+fn join_lines(line: &str) -> String {
+    let joined = line.replace("\\\n", "\n");
+    let trimmed = joined.trim();
+    match trimmed
+        .strip_prefix(r#"""""#)
+        .and_then(|rest| rest.strip_suffix(r#"""""#))
+    {
+        Some(inner) => inner.trim().to_string(),
+        None => trimmed.to_string(),
+    }
+}
+
+fn load_history() -> Result<Vec<String>> {
+    match storage::read(&history_path()?)? {
+        Some(contents) => Ok(serde_json::from_str(&contents)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn save_history(editor: &Editor<ReplHelper, DefaultHistory>) -> Result<()> {
+    let entries = editor.history().into_iter().collect::<Vec<_>>();
+    let start = entries.len().saturating_sub(HISTORY_LIMIT);
+    storage::write(&history_path()?, &serde_json::to_string(&entries[start..])?)
+}
+
+fn history_path() -> Result<PathBuf> {
+    let mut path = paths::home()?;
+    path.push("prompt_history.json");
+    Ok(path)
+}
@@ -0,0 +1,175 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::io::{self, stderr, stdout, Write};
+use std::path::PathBuf;
+
+use crate::llm::Usage;
+use crate::memory::MemoryEntry;
+use crate::paths;
+use crate::storage;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub id: String,
+    pub created: String,
+    pub exchanges: Vec<Exchange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Exchange {
+    pub timestamp: String,
+    pub model: String,
+    pub prompt: String,
+    pub response: String,
+    pub context: ContextRecord,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Time until the full response arrived
+    #[serde(default)]
+    pub latency_ms: u64,
+}
+
+/// What was injected alongside a prompt
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ContextRecord {
+    #[serde(default)]
+    pub persona: Option<String>,
+    pub system_message_version: Option<String>,
+    pub memory_enabled: bool,
+    pub memory_entries: Vec<MemoryEntry>,
+}
+
+impl ContextRecord {
+    pub fn print(&self) {
+        let _ = self.write(&mut stdout());
+    }
+
+    /// Prints to stderr, keeping stdout for the answer
+    pub fn eprint(&self) {
+        let _ = self.write(&mut stderr());
+    }
+
+    fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Context for this response:")?;
+        if let Some(persona) = &self.persona {
+            writeln!(out, "  Persona: {}", persona)?;
+        }
+        match &self.system_message_version {
+            Some(version) => writeln!(out, "  System message: version {}", version)?,
+            None => writeln!(out, "  System message: none")?,
+        }
+        if !self.memory_enabled {
+            writeln!(out, "  Memory: disabled")?;
+        } else if self.memory_entries.is_empty() {
+            writeln!(out, "  Memory: no entries")?;
+        } else {
+            writeln!(out, "  Memory entries:")?;
+            for entry in &self.memory_entries {
+                writeln!(out, "    [{}] {}", entry.id, entry.content)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        let now = Local::now();
+        Self {
+            id: format!(
+                "{}-{:04x}",
+                now.format("%Y%m%d-%H%M%S"),
+                rand::thread_rng().gen::<u16>()
+            ),
+            created: now.to_rfc3339(),
+            exchanges: Vec::new(),
+        }
+    }
+
+    pub fn load(id: &str) -> Result<Self> {
+        match storage::read(&Self::session_path(id)?)? {
+            Some(contents) => Ok(serde_json::from_str(&contents)?),
+            None => bail!("Session not found: {}", id),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::session_path(&self.id)?;
+        storage::write(&path, &serde_json::to_string_pretty(self)?)
+    }
+
+    /// Session ids, oldest first
+    pub fn list() -> Result<Vec<String>> {
+        storage::names(&Self::sessions_dir()?, "json")
+    }
+
+    pub fn record(&mut self, exchange: Exchange) -> Result<()> {
+        self.exchanges.push(exchange);
+        self.save()
+    }
+
+    pub fn last_exchange(&self) -> Option<&Exchange> {
+        self.exchanges.last()
+    }
+
+    fn session_path(id: &str) -> Result<PathBuf> {
+        Self::validate_id(id)?;
+        let mut path = Self::sessions_dir()?;
+        path.push(format!("{}.json", id));
+        Ok(path)
+    }
+
+    /// Checks that `id` has the `YYYYMMDD-HHMMSS-xxxx` shape `new` gives it
+    fn validate_id(id: &str) -> Result<()> {
+        let parts = id.split('-').collect::<Vec<_>>();
+        let valid = match parts.as_slice() {
+            [date, time, suffix] => {
+                date.len() == 8
+                    && time.len() == 6
+                    && suffix.len() == 4
+                    && date.chars().chain(time.chars()).all(|c| c.is_ascii_digit())
+                    && suffix.chars().all(|c| c.is_ascii_hexdigit())
+            }
+            _ => false,
+        };
+        if !valid {
+            bail!("Invalid session id '{}', expected YYYYMMDD-HHMMSS-xxxx", id);
+        }
+        Ok(())
+    }
+
+    fn sessions_dir() -> Result<PathBuf> {
+        let mut path = paths::home()?;
+        path.push("sessions");
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_new_session_ids() {
+        assert!(Session::validate_id(&Session::new().id).is_ok());
+        assert!(Session::validate_id("20240131-235959-0a9f").is_ok());
+    }
+
+    #[test]
+    fn rejects_other_session_ids() {
+        for id in [
+            "../settings",
+            "20240131-235959-0a9f/..",
+            "20240131-235959",
+            "2024-01-31",
+            "",
+        ] {
+            assert!(Session::load(id).is_err(), "{id}");
+            assert!(Session::validate_id(id).is_err(), "{id}");
+        }
+    }
+}
@@ -1,62 +1,309 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
 use std::fs;
 use std::path::PathBuf;
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::config;
+use crate::paths;
+use crate::storage;
+
+/// Schema version written by this build
+pub const CURRENT_VERSION: u32 = 5;
+
+// MIGRATIONS[n] upgrades a version n document to version n + 1
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[
+    migrate_v0_to_v1,
+    migrate_v1_to_v2,
+    migrate_v2_to_v3,
+    migrate_v3_to_v4,
+    migrate_v4_to_v5,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Settings {
+    pub version: u32,
     pub model: String,
     pub use_memory: bool,
+    pub memory_sync: bool,
+    pub memory_sync_remote: Option<String>,
+    pub persona: Option<String>,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// Shell command whose output is the API key, e.g. `pass show openai`
+    pub api_key_command: Option<String>,
+    /// Append commands run by `terminus cmd` to the shell's history file
+    pub shell_history: bool,
+    /// Fields this build doesn't know about, kept so newer builds don't lose them
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Self {
+            version: CURRENT_VERSION,
             model: "gpt-4o-mini".to_string(),
             use_memory: true,
+            memory_sync: false,
+            memory_sync_remote: None,
+            persona: None,
+            temperature: None,
+            top_p: None,
+            api_key_command: None,
+            shell_history: false,
+            extra: Map::new(),
         }
     }
 }
 
 impl Settings {
+    /// Effective settings after applying project, environment and command line layers
     pub fn load() -> Result<Self> {
-        let path = Self::settings_path()?;
-        if path.exists() {
-            let contents = fs::read_to_string(path)?;
-            // Try to parse with current schema, if fails, attempt migration
-            match serde_json::from_str(&contents) {
-                Ok(settings) => Ok(settings),
-                Err(_) => {
-                    // Parse as legacy format (just model field)
-                    #[derive(Deserialize)]
-                    struct LegacySettings {
-                        model: String,
-                    }
-                    let legacy: LegacySettings = serde_json::from_str(&contents)?;
-                    let settings = Self {
-                        model: legacy.model,
-                        use_memory: true, // default value for migrated settings
-                    };
-                    settings.save()?; // Save with new schema
-                    Ok(settings)
-                }
-            }
-        } else {
-            Ok(Self::default())
+        Ok(config::resolve()?.settings)
+    }
+
+    /// Only the global settings file, for changes that should be saved back
+    pub fn load_global() -> Result<Self> {
+        let path = Self::global_path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let _lock = storage::lock()?;
+        let contents = fs::read_to_string(&path)?;
+        let value: Value = serde_json::from_str(&contents)
+            .with_context(|| format!("{} is not valid JSON", path.display()))?;
+        let (settings, from_version) = Self::migrate(value)?;
+        if from_version < CURRENT_VERSION {
+            // Keep the original around in case the migration loses something
+            let backup = path.with_file_name(format!("settings.v{}.json.bak", from_version));
+            storage::write_atomic(&backup, contents.as_bytes())?;
+            settings.save()?;
         }
+        Ok(settings)
+    }
+
+    /// Upgrades a settings document of any known version, returning the
+    /// settings and the version the document was written with
+    pub fn migrate(value: Value) -> Result<(Self, u32)> {
+        let Value::Object(mut fields) = value else {
+            bail!("Settings must be a JSON object");
+        };
+
+        let from_version = match fields.get("version") {
+            Some(version) => version
+                .as_u64()
+                .and_then(|v| u32::try_from(v).ok())
+                .context("Settings version must be a non-negative integer")?,
+            // Files from before the version field existed
+            None if fields.contains_key("use_memory") => 1,
+            None => 0,
+        };
+        if from_version > CURRENT_VERSION {
+            bail!(
+                "Settings were written by a newer Terminus (schema version {}, this build supports {})",
+                from_version,
+                CURRENT_VERSION
+            );
+        }
+
+        for migration in &MIGRATIONS[from_version as usize..] {
+            migration(&mut fields);
+        }
+        fields.insert("version".to_string(), json!(CURRENT_VERSION));
+
+        let settings = serde_json::from_value(Value::Object(fields))
+            .context("Settings file has an invalid shape")?;
+        Ok((settings, from_version))
+    }
+
+    /// Applies `change` to the global settings file
+    pub fn update(change: impl FnOnce(&mut Self)) -> Result<Self> {
+        let _lock = storage::lock()?;
+        let mut settings = Self::load_global()?;
+        change(&mut settings);
+        settings.save()?;
+        Ok(settings)
     }
 
     pub fn save(&self) -> Result<()> {
-        let path = Self::settings_path()?;
-        fs::create_dir_all(path.parent().unwrap())?;
-        fs::write(path, serde_json::to_string_pretty(self)?)?;
-        Ok(())
+        storage::write_atomic(
+            &Self::global_path()?,
+            serde_json::to_string_pretty(self)?.as_bytes(),
+        )
     }
 
-    fn settings_path() -> Result<PathBuf> {
-        let mut path = dirs::config_dir().expect("Failed to get config directory");
-        path.push("terminus");
+    pub fn global_path() -> Result<PathBuf> {
+        let mut path = paths::home()?;
         path.push("settings.json");
         Ok(path)
     }
 }
+
+/// v0 only stored the model
+fn migrate_v0_to_v1(fields: &mut Map<String, Value>) {
+    fields.entry("use_memory").or_insert_with(|| json!(true));
+}
+
+/// v2 adds the schema version, memory sync and personas
+fn migrate_v1_to_v2(fields: &mut Map<String, Value>) {
+    fields.entry("memory_sync").or_insert_with(|| json!(false));
+    fields.entry("memory_sync_remote").or_insert(Value::Null);
+    fields.entry("persona").or_insert(Value::Null);
+}
+
+/// v3 adds default sampling parameters
+fn migrate_v2_to_v3(fields: &mut Map<String, Value>) {
+    fields.entry("temperature").or_insert(Value::Null);
+    fields.entry("top_p").or_insert(Value::Null);
+}
+
+/// v4 adds the API key helper command
+fn migrate_v3_to_v4(fields: &mut Map<String, Value>) {
+    fields.entry("api_key_command").or_insert(Value::Null);
+}
+
+/// v5 adds recording `terminus cmd` commands in shell history
+fn migrate_v4_to_v5(fields: &mut Map<String, Value>) {
+    fields
+        .entry("shell_history")
+        .or_insert_with(|| json!(false));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrates_v0_model_only() {
+        let (settings, from) = Settings::migrate(json!({ "model": "gpt-4o" })).unwrap();
+        assert_eq!(from, 0);
+        assert_eq!(settings.version, CURRENT_VERSION);
+        assert_eq!(settings.model, "gpt-4o");
+        assert!(settings.use_memory);
+        assert!(!settings.memory_sync);
+        assert_eq!(settings.persona, None);
+    }
+
+    #[test]
+    fn migrates_v1_without_version_field() {
+        let (settings, from) =
+            Settings::migrate(json!({ "model": "gpt-4o-mini", "use_memory": false })).unwrap();
+        assert_eq!(from, 1);
+        assert!(!settings.use_memory);
+        assert!(!settings.memory_sync);
+        assert_eq!(settings.memory_sync_remote, None);
+    }
+
+    #[test]
+    fn migrates_v1_with_optional_fields() {
+        let (settings, from) = Settings::migrate(json!({
+            "model": "gpt-4o",
+            "use_memory": true,
+            "memory_sync": true,
+            "memory_sync_remote": "/srv/memory",
+            "persona": "pirate"
+        }))
+        .unwrap();
+        assert_eq!(from, 1);
+        assert!(settings.memory_sync);
+        assert_eq!(settings.memory_sync_remote.as_deref(), Some("/srv/memory"));
+        assert_eq!(settings.persona.as_deref(), Some("pirate"));
+    }
+
+    #[test]
+    fn migrates_v2_without_sampling() {
+        let (settings, from) = Settings::migrate(json!({
+            "version": 2,
+            "model": "gpt-4o",
+            "use_memory": false,
+            "memory_sync": false,
+            "memory_sync_remote": null,
+            "persona": null
+        }))
+        .unwrap();
+        assert_eq!(from, 2);
+        assert_eq!(settings.version, CURRENT_VERSION);
+        assert!(!settings.use_memory);
+        assert_eq!(settings.temperature, None);
+        assert_eq!(settings.top_p, None);
+    }
+
+    #[test]
+    fn migrates_v3_without_api_key_command() {
+        let (settings, from) = Settings::migrate(json!({
+            "version": 3,
+            "model": "gpt-4o",
+            "use_memory": true,
+            "memory_sync": false,
+            "memory_sync_remote": null,
+            "persona": null,
+            "temperature": 0.5,
+            "top_p": null
+        }))
+        .unwrap();
+        assert_eq!(from, 3);
+        assert_eq!(settings.temperature, Some(0.5));
+        assert_eq!(settings.api_key_command, None);
+    }
+
+    #[test]
+    fn migrates_v4_without_shell_history() {
+        let (settings, from) = Settings::migrate(json!({
+            "version": 4,
+            "model": "gpt-4o",
+            "use_memory": true,
+            "memory_sync": false,
+            "memory_sync_remote": null,
+            "persona": null,
+            "temperature": null,
+            "top_p": null,
+            "api_key_command": "pass show openai"
+        }))
+        .unwrap();
+        assert_eq!(from, 4);
+        assert_eq!(
+            settings.api_key_command.as_deref(),
+            Some("pass show openai")
+        );
+        assert!(!settings.shell_history);
+    }
+
+    #[test]
+    fn loads_current_version_unchanged() {
+        let current = serde_json::to_value(Settings::default()).unwrap();
+        let (settings, from) = Settings::migrate(current).unwrap();
+        assert_eq!(from, CURRENT_VERSION);
+        assert_eq!(settings.model, "gpt-4o-mini");
+    }
+
+    #[test]
+    fn preserves_unknown_fields() {
+        let (settings, _) = Settings::migrate(json!({
+            "model": "gpt-4o",
+            "use_memory": true,
+            "future_option": { "enabled": true }
+        }))
+        .unwrap();
+        assert_eq!(settings.extra["future_option"], json!({ "enabled": true }));
+
+        let saved = serde_json::to_value(&settings).unwrap();
+        assert_eq!(saved["future_option"], json!({ "enabled": true }));
+        assert_eq!(saved["version"], json!(CURRENT_VERSION));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let error =
+            Settings::migrate(json!({ "version": CURRENT_VERSION + 1, "model": "x" })).unwrap_err();
+        assert!(error.to_string().contains("newer Terminus"));
+    }
+
+    #[test]
+    fn rejects_invalid_shapes() {
+        assert!(Settings::migrate(json!(["gpt-4o"])).is_err());
+        assert!(Settings::migrate(json!({ "use_memory": true })).is_err());
+        assert!(Settings::migrate(json!({ "version": "two", "model": "x" })).is_err());
+    }
+}
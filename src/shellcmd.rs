@@ -0,0 +1,362 @@
+use anyhow::{bail, Context, Result};
+use chrono::Local;
+use crossterm::style::Stylize;
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{stderr, stdin, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::llm::{self, OpenAIClient};
+
+const COMMAND_PROMPT: &str = r#"You turn requests into a single command for {shell} on {os}.
+Reply with JSON only, using this shape:
+{
+  "command": "the command, on one line",
+  "explanation": "what the command does, in one or two sentences",
+  "risk": "read-only" or "destructive"
+}
+Use "destructive" for anything that deletes, overwrites, moves or changes files,
+permissions, processes, packages or system settings. If the request can't be done
+with one command, leave "command" empty and say why in "explanation"."#;
+
+// Programs that only read, whatever their arguments. Anything not listed here
+// or in `read_only` counts as destructive
+const READ_ONLY_PROGRAMS: &[&str] = &[
+    "basename",
+    "cat",
+    "cmp",
+    "column",
+    "cut",
+    "df",
+    "diff",
+    "dirname",
+    "du",
+    "echo",
+    "egrep",
+    "fgrep",
+    "free",
+    "grep",
+    "head",
+    "id",
+    "jq",
+    "less",
+    "ls",
+    "lsblk",
+    "lsof",
+    "man",
+    "md5sum",
+    "more",
+    "nl",
+    "printenv",
+    "ps",
+    "pwd",
+    "readlink",
+    "realpath",
+    "sha1sum",
+    "sha256sum",
+    "stat",
+    "tail",
+    "tr",
+    "uname",
+    "uptime",
+    "wc",
+    "which",
+    "whereis",
+    "whoami",
+];
+
+// `find` flags that run commands, delete files or write to files
+const FIND_ACTIONS: &[&str] = &[
+    "-delete", "-exec", "-execdir", "-ok", "-okdir", "-fls", "-fprint", "-fprint0", "-fprintf",
+];
+
+// git subcommands that only read
+const GIT_READ_ONLY: &[&str] = &[
+    "blame",
+    "describe",
+    "diff",
+    "grep",
+    "log",
+    "ls-files",
+    "rev-parse",
+    "shortlog",
+    "show",
+    "status",
+];
+
+#[derive(Deserialize)]
+struct Suggestion {
+    command: String,
+    explanation: String,
+    risk: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Risk {
+    ReadOnly,
+    Destructive,
+}
+
+impl fmt::Display for Risk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Risk::ReadOnly => write!(f, "read-only"),
+            Risk::Destructive => write!(f, "destructive"),
+        }
+    }
+}
+
+/// The shell commands are written for and run with
+struct Shell {
+    name: String,
+    program: PathBuf,
+}
+
+impl Shell {
+    /// `$SHELL`, or PowerShell or cmd on Windows
+    fn detect() -> Self {
+        if cfg!(windows) {
+            let name = if env::var_os("PSModulePath").is_some() {
+                "powershell"
+            } else {
+                "cmd"
+            };
+            return Self {
+                name: name.to_string(),
+                program: PathBuf::from(name),
+            };
+        }
+        let program = env::var_os("SHELL")
+            .filter(|shell| !shell.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/bin/sh"));
+        let name = program
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "sh".to_string());
+        Self { name, program }
+    }
+
+    fn command(&self, line: &str) -> Command {
+        let mut command = Command::new(&self.program);
+        match self.name.as_str() {
+            "cmd" => command.arg("/C"),
+            "powershell" | "pwsh" => command.arg("-Command"),
+            _ => command.arg("-c"),
+        };
+        command.arg(line);
+        command
+    }
+
+    /// Appends `line` to the shell's history file in the format it reads back.
+    /// A shell that's already running only sees it once it reloads its history
+    fn record(&self, line: &str) -> Result<()> {
+        let home = dirs::home_dir().context("Could not locate your home directory")?;
+        let histfile = env::var_os("HISTFILE").map(PathBuf::from);
+        let timestamp = Local::now().timestamp();
+        let (path, entry) = match self.name.as_str() {
+            "bash" => (
+                histfile.unwrap_or_else(|| home.join(".bash_history")),
+                format!("{}\n", line),
+            ),
+            "zsh" => (
+                histfile.unwrap_or_else(|| home.join(".zsh_history")),
+                format!(": {}:0;{}\n", timestamp, line),
+            ),
+            "fish" => (
+                dirs::data_dir()
+                    .unwrap_or_else(|| home.join(".local/share"))
+                    .join("fish/fish_history"),
+                format!("- cmd: {}\n  when: {}\n", line, timestamp),
+            ),
+            name => bail!("Recording history isn't supported for {}", name),
+        };
+        append(&path, &entry)
+    }
+}
+
+/// Asks the model for a command that does what `request` describes, shows it
+/// with its risk, and runs it once the user confirms
+pub async fn run(client: &OpenAIClient, request: &str, record_history: bool) -> Result<()> {
+    let shell = Shell::detect();
+    let system_message = COMMAND_PROMPT
+        .replace("{shell}", &shell.name)
+        .replace("{os}", env::consts::OS);
+    let response = client
+        .complete_with_system(request, &system_message, None)
+        .await?;
+    let suggestion: Suggestion = serde_json::from_str(llm::strip_code_fence(&response.content))
+        .context("Model returned an invalid command suggestion")?;
+    let line = suggestion.command.trim();
+    if line.is_empty() {
+        bail!("No command suggested: {}", suggestion.explanation);
+    }
+
+    // Trust the model's classification only when it agrees with our own
+    let risk = if suggestion.risk == "read-only" && classify(line) == Risk::ReadOnly {
+        Risk::ReadOnly
+    } else {
+        Risk::Destructive
+    };
+
+    // Without a terminal to confirm on, print the command instead of running it
+    if !stdin().is_terminal() {
+        println!("{}", line);
+        return Ok(());
+    }
+
+    eprintln!("\n  {}\n", line.bold());
+    eprintln!("{}", suggestion.explanation);
+    match risk {
+        Risk::ReadOnly => eprintln!("Risk: {}", risk.to_string().green()),
+        Risk::Destructive => eprintln!("Risk: {}", risk.to_string().red().bold()),
+    }
+    let confirmed = match risk {
+        Risk::ReadOnly => ask("Run it? [y/N]: ")?.eq_ignore_ascii_case("y"),
+        Risk::Destructive => {
+            ask("This may change or delete data. Type `yes` to run it: ")? == "yes"
+        }
+    };
+    if !confirmed {
+        eprintln!("Command not run");
+        return Ok(());
+    }
+
+    let status = shell
+        .command(line)
+        .status()
+        .with_context(|| format!("Failed to start {}", shell.program.display()))?;
+    if record_history {
+        if let Err(error) = shell.record(line) {
+            eprintln!("Warning: {:#}", error);
+        }
+    }
+    if !status.success() {
+        bail!("Command exited with {}", status);
+    }
+    Ok(())
+}
+
+/// A conservative check for commands that change something: a command is
+/// read-only only if every part of it runs a known read-only program and it
+/// doesn't write to files through redirection
+fn classify(line: &str) -> Risk {
+    // Command and process substitution can run anything
+    if ["$(", "`", "<(", ">("].iter().any(|s| line.contains(s)) {
+        return Risk::Destructive;
+    }
+    let redirects = line.match_indices('>').any(|(i, _)| {
+        let target = line[i + 1..].trim_start_matches(['>', '|']).trim_start();
+        !target.starts_with('&') && !target.starts_with("/dev/null")
+    });
+    let read_only = line
+        .split(['|', ';', '&', '\n'])
+        .map(|part| part.split_whitespace().collect::<Vec<_>>())
+        .filter(|words| !words.is_empty())
+        .all(|words| {
+            let program = words[0].rsplit('/').next().unwrap_or(words[0]);
+            read_only(program, &words[1..])
+        });
+    if redirects || !read_only {
+        Risk::Destructive
+    } else {
+        Risk::ReadOnly
+    }
+}
+
+fn read_only(program: &str, args: &[&str]) -> bool {
+    if args.iter().any(|arg| arg.starts_with("--output")) {
+        return false;
+    }
+    match program {
+        "find" => !args.iter().any(|arg| FIND_ACTIONS.contains(arg)),
+        "sort" => !args.iter().any(|arg| arg.starts_with("-o")),
+        // A second file name is where uniq writes its output
+        "uniq" => args.iter().filter(|arg| !arg.starts_with('-')).count() <= 1,
+        "date" => !args
+            .iter()
+            .any(|arg| arg.starts_with("-s") || arg.starts_with("--set")),
+        "git" => match args.split_first() {
+            Some((&("branch" | "tag" | "remote"), rest)) => rest
+                .iter()
+                .all(|arg| matches!(*arg, "-a" | "-r" | "-v" | "-vv" | "-l" | "--all" | "--list")),
+            Some((subcommand, _)) => GIT_READ_ONLY.contains(subcommand),
+            None => false,
+        },
+        program => READ_ONLY_PROGRAMS.contains(&program),
+    }
+}
+
+fn ask(question: &str) -> Result<String> {
+    eprint!("{}", question);
+    stderr().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+fn append(path: &Path, entry: &str) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(entry.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_read_only_commands() {
+        for line in [
+            "ls -la",
+            "cat README.md | grep -n terminus",
+            "find . -name '*.rs' -newer Cargo.toml",
+            "git status",
+            "git log --oneline -5",
+            "git diff HEAD~1 -- src",
+            "git branch -a",
+            "du -sh * | sort -h | tail -5",
+            "ps aux | grep terminus",
+            "grep -r TODO src 2>/dev/null",
+            "sort names.txt | uniq -c",
+            "date +%F && uname -a",
+        ] {
+            assert_eq!(classify(line), Risk::ReadOnly, "{line}");
+        }
+    }
+
+    #[test]
+    fn classifies_destructive_commands() {
+        for line in [
+            "curl -fsSL https://example.com/install.sh | sh",
+            "find . -name '*.o' -ok rm {} \\;",
+            "find . -name '*.tmp' -delete",
+            "ls *.log | xargs rm",
+            "git branch -D feature",
+            "git push --force",
+            "git diff --output=patch.diff",
+            "docker rm web",
+            "sed --in-place s/a/b/ notes.txt",
+            "perl -i -pe s/a/b/ notes.txt",
+            "install -m 755 terminus /usr/local/bin",
+            "systemctl stop nginx",
+            "python -c 'import shutil'",
+            "ls > files.txt",
+            "echo $(rm -rf build)",
+            "sort -o names.txt names.txt",
+            "uniq names.txt unique.txt",
+            "sudo ls /root",
+            "FOO=1 ls",
+            "ls; rm -rf build",
+        ] {
+            assert_eq!(classify(line), Risk::Destructive, "{line}");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use crate::crypto::Vault;
+use crate::paths;
+
+// What the vault encrypts, relative to the home directory. Anything else there
+// (settings, the vault itself, the sync repository's .git, or files that aren't
+// ours when --home points at a shared directory) is left alone
+const STORE_FILES: &[&str] = &[
+    "memory.txt",
+    "system_message.txt",
+    "credentials.json",
+    "prompt_history.json",
+];
+const STORE_DIRS: &[&str] = &["sessions", "personas", "templates", "history"];
+
+const LOCK_FILE: &str = ".lock";
+
+// How many guards this process holds, and the locked file while any are held
+static LOCK: Mutex<(usize, Option<File>)> = parking_lot::const_mutex((0, None));
+
+/// Exclusive advisory lock on the store, released when dropped
+pub struct StoreLock;
+
+impl Drop for StoreLock {
+    fn drop(&mut self) {
+        let mut lock = LOCK.lock();
+        lock.0 -= 1;
+        if lock.0 == 0 {
+            if let Some(file) = lock.1.take() {
+                let _ = file.unlock();
+            }
+        }
+    }
+}
+
+/// Locks the store against other Terminus processes for a read-modify-write.
+/// Nested calls within one process share the same lock.
+pub fn lock() -> Result<StoreLock> {
+    let mut lock = LOCK.lock();
+    if lock.1.is_none() {
+        let dir = paths::home()?;
+        fs::create_dir_all(&dir)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(dir.join(LOCK_FILE))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                if io::stderr().is_terminal() {
+                    eprintln!("Waiting for another Terminus process to finish...");
+                }
+                file.lock()?;
+            }
+            Err(TryLockError::Error(error)) => {
+                return Err(error).context("Failed to lock the Terminus store")
+            }
+        }
+        lock.1 = Some(file);
+    }
+    lock.0 += 1;
+    Ok(StoreLock)
+}
+
+/// Reads a store file, transparently decrypting it when needed
+pub fn read(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(path)?;
+    let contents = decode(data)?;
+    Ok(Some(String::from_utf8(contents).with_context(|| {
+        format!("{} is not valid UTF-8", path.display())
+    })?))
+}
+
+/// Names of the files in `dir` with `extension`, without it, sorted. Only the
+/// directory is read, so listing never decrypts anything
+pub fn names(dir: &Path, extension: &str) -> Result<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let suffix = format!(".{}", extension);
+    let mut names = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.strip_suffix(&suffix).map(str::to_string)
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    Ok(names)
+}
+
+/// Writes a store file, encrypting it when encryption at rest is enabled
+pub fn write(path: &Path, contents: &str) -> Result<()> {
+    fs::create_dir_all(path.parent().unwrap())?;
+    let data = if Vault::is_enabled()? {
+        Vault::encrypt(contents.as_bytes())?
+    } else {
+        contents.as_bytes().to_vec()
+    };
+    write_atomic(path, &data)
+}
+
+/// Replaces a file by writing a temporary file beside it and renaming it into
+/// place, so readers never see a partial write
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(data)?;
+    file.as_file().sync_all()?;
+    file.persist(path)
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    Ok(())
+}
+
+pub fn decode(data: Vec<u8>) -> Result<Vec<u8>> {
+    if Vault::is_encrypted(&data) {
+        Vault::decrypt(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+/// Short stable identifier for a piece of content (FNV-1a)
+pub fn fingerprint(content: &str) -> String {
+    let hash = content
+        .trim()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)[..8].to_string()
+}
+
+/// Encrypts every existing store file in place, returning how many were rewritten
+pub fn encrypt_all(passphrase: &str) -> Result<usize> {
+    let _lock = lock()?;
+    Vault::create(passphrase)?;
+    let mut count = 0;
+    for path in store_files()? {
+        let data = fs::read(&path)?;
+        if !Vault::is_encrypted(&data) {
+            write_atomic(&path, &Vault::encrypt(&data)?)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Decrypts every store file in place and removes the vault
+pub fn decrypt_all() -> Result<usize> {
+    let _lock = lock()?;
+    let mut count = 0;
+    for path in store_files()? {
+        let data = fs::read(&path)?;
+        if Vault::is_encrypted(&data) {
+            write_atomic(&path, &Vault::decrypt(&data)?)?;
+            count += 1;
+        }
+    }
+    Vault::remove()?;
+    Ok(count)
+}
+
+fn store_files() -> Result<Vec<PathBuf>> {
+    let home = paths::home()?;
+    let mut files = STORE_FILES
+        .iter()
+        .map(|name| home.join(name))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    for name in STORE_DIRS {
+        let dir = home.join(name);
+        if dir.is_dir() {
+            collect_files(&dir, &mut files)?;
+        }
+    }
+    Ok(files)
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,210 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::git;
+use crate::memory::{Memory, MemoryEntry};
+use crate::paths;
+use crate::settings::Settings;
+use crate::storage;
+
+const MEMORY_FILE: &str = "memory.txt";
+
+// Only the memory store is tracked; settings, sessions and the vault stay local
+const GITIGNORE: &str = "*\n!.gitignore\n!memory.txt\n";
+
+pub struct MemorySync;
+
+pub struct MergeSummary {
+    pub added: usize,
+    pub removed: usize,
+}
+
+impl MemorySync {
+    /// Turns the config directory into a git repository tracking the memory store
+    pub fn enable(remote: Option<String>) -> Result<()> {
+        let dir = Self::repo_dir()?;
+        fs::create_dir_all(&dir)?;
+        if !dir.join(".git").exists() {
+            git::run(&dir, &["init", "--quiet"])?;
+            git::run(&dir, &["config", "user.name", "Terminus"])?;
+            git::run(&dir, &["config", "user.email", "terminus@localhost"])?;
+        }
+        fs::write(dir.join(".gitignore"), GITIGNORE)?;
+
+        Settings::update(|settings| {
+            settings.memory_sync = true;
+            if remote.is_some() {
+                settings.memory_sync_remote = remote;
+            }
+        })?;
+
+        Self::commit("Enable memory sync")
+    }
+
+    pub fn disable() -> Result<()> {
+        Settings::update(|settings| settings.memory_sync = false)?;
+        Ok(())
+    }
+
+    /// Commits the memory store if it changed since the last commit. Does
+    /// nothing until `enable` has made the directory a repository, so git
+    /// never falls back to a repository that happens to enclose it
+    pub fn commit(message: &str) -> Result<()> {
+        let dir = Self::repo_dir()?;
+        if !dir.join(".git").exists() {
+            eprintln!(
+                "Warning: memory sync is on but {} is not a git repository; \
+                 run `terminus memory sync enable`",
+                dir.display()
+            );
+            return Ok(());
+        }
+        git::run(&dir, &["add", "--all"])?;
+        if git::run(&dir, &["status", "--porcelain"])?
+            .trim()
+            .is_empty()
+        {
+            return Ok(());
+        }
+        git::run(&dir, &["commit", "--quiet", "-m", message])?;
+        Ok(())
+    }
+
+    /// Fetches the remote memory repository and merges it entry by entry
+    pub fn pull(remote: &str) -> Result<MergeSummary> {
+        let dir = Self::repo_dir()?;
+        if !dir.join(".git").exists() {
+            bail!("Memory sync is not enabled; run `terminus memory sync enable` first");
+        }
+        let _lock = storage::lock()?;
+        Self::commit("Update memory")?;
+        git::run(&dir, &["fetch", "--quiet", "--", remote, "HEAD"])?;
+
+        let base = git::run(&dir, &["merge-base", "HEAD", "FETCH_HEAD"])
+            .ok()
+            .map(|base| base.trim().to_string());
+        if base.as_deref() == Some(git::run(&dir, &["rev-parse", "FETCH_HEAD"])?.trim()) {
+            return Ok(MergeSummary {
+                added: 0,
+                removed: 0,
+            });
+        }
+
+        let ours = Memory::entries()?;
+        let theirs = Self::entries_at(&dir, "FETCH_HEAD")?;
+        let base = match &base {
+            Some(base) => Self::entries_at(&dir, base)?,
+            None => Vec::new(),
+        };
+        let (merged, summary) = merge_entries(&base, &ours, &theirs);
+
+        // Record the remote as a parent so the next merge starts from the right base
+        git::run(
+            &dir,
+            &[
+                "merge",
+                "--quiet",
+                "--no-commit",
+                "--no-ff",
+                "--allow-unrelated-histories",
+                "-s",
+                "ours",
+                "FETCH_HEAD",
+            ],
+        )?;
+        storage::write(&dir.join(MEMORY_FILE), &Memory::join(&merged))?;
+        git::run(&dir, &["add", MEMORY_FILE])?;
+        git::run(
+            &dir,
+            &[
+                "commit",
+                "--quiet",
+                "-m",
+                &format!("Merge memory from {}", remote),
+            ],
+        )?;
+        Ok(summary)
+    }
+
+    fn entries_at(dir: &Path, revision: &str) -> Result<Vec<MemoryEntry>> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["show", &format!("{}:{}", revision, MEMORY_FILE)])
+            .output()?;
+        if !output.status.success() {
+            // The file didn't exist at that revision
+            return Ok(Vec::new());
+        }
+        let contents = String::from_utf8(storage::decode(output.stdout)?)
+            .context("Memory store in git history is not valid UTF-8")?;
+        Ok(Memory::parse(&contents))
+    }
+
+    fn repo_dir() -> Result<PathBuf> {
+        paths::home()
+    }
+}
+
+/// Three-way merge keyed by entry id: an entry survives unless one side removed it.
+/// Ids are fingerprints of the text, so an edit looks like removing the old
+/// entry and adding a new one; when both sides edit the same entry, both
+/// edits survive. `memory sync --help` tells users about this
+fn merge_entries(
+    base: &[MemoryEntry],
+    ours: &[MemoryEntry],
+    theirs: &[MemoryEntry],
+) -> (Vec<MemoryEntry>, MergeSummary) {
+    let contains = |entries: &[MemoryEntry], id: &str| entries.iter().any(|e| e.id == id);
+    let mut summary = MergeSummary {
+        added: 0,
+        removed: 0,
+    };
+
+    let mut merged = Vec::new();
+    for entry in ours {
+        if contains(base, &entry.id) && !contains(theirs, &entry.id) {
+            summary.removed += 1;
+        } else {
+            merged.push(entry.clone());
+        }
+    }
+    for entry in theirs {
+        if !contains(ours, &entry.id) && !contains(base, &entry.id) {
+            merged.push(entry.clone());
+            summary.added += 1;
+        }
+    }
+    (merged, summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(contents: &[&str]) -> Vec<MemoryEntry> {
+        contents
+            .iter()
+            .map(|content| MemoryEntry::new(content))
+            .collect()
+    }
+
+    fn merge(base: &[&str], ours: &[&str], theirs: &[&str]) -> Vec<String> {
+        let (merged, _) = merge_entries(&entries(base), &entries(ours), &entries(theirs));
+        merged.into_iter().map(|entry| entry.content).collect()
+    }
+
+    #[test]
+    fn takes_edits_made_on_one_side() {
+        assert_eq!(merge(&["a", "b"], &["a", "b2"], &["a", "b"]), ["a", "b2"]);
+        assert_eq!(merge(&["a", "b"], &["a", "b"], &["a", "b2"]), ["a", "b2"]);
+        assert_eq!(merge(&["a", "b"], &["a"], &["a", "b", "c"]), ["a", "c"]);
+    }
+
+    #[test]
+    fn keeps_both_edits_of_the_same_entry() {
+        assert_eq!(merge(&["a"], &["a1"], &["a2"]), ["a1", "a2"]);
+    }
+}
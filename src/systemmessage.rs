@@ -1,29 +1,63 @@
 use anyhow::Result;
-use std::fs;
 use std::path::PathBuf;
 
+use crate::history::History;
+use crate::paths;
+use crate::persona::Persona;
+use crate::storage;
+use crate::template;
+
 pub struct SystemMessage;
 
 impl SystemMessage {
+    /// Loads the active system message with its template variables filled in
     pub fn load() -> Result<String> {
-        let path = Self::message_path()?;
-        if path.exists() {
-            Ok(fs::read_to_string(path)?)
-        } else {
-            Ok(String::new())
+        Self::render(&Self::load_raw()?)
+    }
+
+    /// Renders runtime variables such as `{{date}}` or `{{env.NAME}}`
+    pub fn render(message: &str) -> Result<String> {
+        template::render(message, &template::runtime_variable)
+    }
+
+    /// Loads the active persona's system message, or the default one, as stored
+    pub fn load_raw() -> Result<String> {
+        match Persona::active()? {
+            Some(persona) => Ok(persona.system_message),
+            None => {
+                let path = Self::message_path()?;
+                Ok(storage::read(&path)?.unwrap_or_default())
+            }
         }
     }
 
+    /// Saves to the active persona, or to the default system message
     pub fn save(message: &str) -> Result<()> {
+        match Persona::active()? {
+            Some(mut persona) => {
+                persona.system_message = message.to_string();
+                persona.save()
+            }
+            None => Self::save_default(message),
+        }
+    }
+
+    /// Saves the default system message, keeping the previous one in history
+    pub fn save_default(message: &str) -> Result<()> {
+        template::validate(message)?;
+        let _lock = storage::lock()?;
         let path = Self::message_path()?;
-        fs::create_dir_all(path.parent().unwrap())?;
-        fs::write(path, message)?;
-        Ok(())
+        let previous = storage::read(&path)?;
+        storage::write(&path, message)?;
+        History::default_system().record(
+            previous.as_deref().map(|previous| (previous, previous)),
+            message,
+            message,
+        )
     }
 
     fn message_path() -> Result<PathBuf> {
-        let mut path = dirs::config_dir().expect("Failed to get config directory");
-        path.push("terminus");
+        let mut path = paths::home()?;
         path.push("system_message.txt");
         Ok(path)
     }
@@ -0,0 +1,258 @@
+use anyhow::{bail, Result};
+use chrono::Local;
+use std::env;
+use std::process::Command;
+
+// Parsed template: `{{name}}` placeholders and `{{#if name}}...{{else}}...{{/if}}` sections
+// (or `{{#unless name}}...{{/unless}}`). `\{{` stands for a literal `{{`
+enum Node {
+    Text(String),
+    Var(String),
+    If {
+        name: String,
+        negate: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// Renders `template`, resolving each placeholder through `lookup`.
+/// Unknown placeholders render as empty strings and count as false in conditions.
+pub fn render(template: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let tokens = tokenize(template)?;
+    let mut position = 0;
+    let (nodes, end) = parse(&tokens, &mut position)?;
+    if let Some(tag) = end {
+        bail!("Unexpected {{{{{}}}}} in template", tag);
+    }
+    let mut output = String::new();
+    evaluate(&nodes, lookup, &mut output);
+    Ok(output)
+}
+
+/// Names of all placeholders and conditions used in `template`, in order of appearance
+pub fn placeholders(template: &str) -> Result<Vec<String>> {
+    let mut names: Vec<String> = Vec::new();
+    for token in tokenize(template)? {
+        if let Token::Tag(tag) = token {
+            let name = tag
+                .strip_prefix("#if ")
+                .or_else(|| tag.strip_prefix("#unless "))
+                .unwrap_or(&tag)
+                .trim();
+            if !name.starts_with('/') && name != "else" && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Checks that `template` is well formed without resolving any variables
+pub fn validate(template: &str) -> Result<()> {
+    render(template, &|_| None).map(|_| ())
+}
+
+/// Built-in runtime variables available to system messages
+pub fn runtime_variable(name: &str) -> Option<String> {
+    if let Some(var) = name.strip_prefix("env.") {
+        return env::var(var).ok();
+    }
+    match name {
+        "date" => Some(Local::now().format("%Y-%m-%d").to_string()),
+        "time" => Some(Local::now().format("%H:%M").to_string()),
+        "cwd" => env::current_dir().ok().map(|dir| dir.display().to_string()),
+        "os" => Some(env::consts::OS.to_string()),
+        "user" => env::var("USER").or_else(|_| env::var("USERNAME")).ok(),
+        "git_branch" => Command::new("git")
+            .args(["rev-parse", "--abbrev-ref", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string()),
+        _ => None,
+    }
+}
+
+enum Token {
+    Text(String),
+    Tag(String),
+}
+
+fn tokenize(template: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            tokens.push(Token::Text(format!("{}{{{{", &rest[..start - 1])));
+            rest = &rest[start + 2..];
+            continue;
+        }
+        if start > 0 {
+            tokens.push(Token::Text(rest[..start].to_string()));
+        }
+        let Some(end) = rest[start..].find("}}") else {
+            bail!("Unclosed '{{{{' in template");
+        };
+        tokens.push(Token::Tag(rest[start + 2..start + end].trim().to_string()));
+        rest = &rest[start + end + 2..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Text(rest.to_string()));
+    }
+    Ok(tokens)
+}
+
+// Parses until a closing `{{/if}}`/`{{else}}` or the end, returning which tag stopped it
+fn parse(tokens: &[Token], position: &mut usize) -> Result<(Vec<Node>, Option<String>)> {
+    let mut nodes = Vec::new();
+    while *position < tokens.len() {
+        let token = &tokens[*position];
+        *position += 1;
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text.clone())),
+            Token::Tag(tag) if tag == "else" || tag.starts_with('/') => {
+                return Ok((nodes, Some(tag.clone())));
+            }
+            Token::Tag(tag) => {
+                let (name, negate, closer) = if let Some(name) = tag.strip_prefix("#if ") {
+                    (name.trim(), false, "/if")
+                } else if let Some(name) = tag.strip_prefix("#unless ") {
+                    (name.trim(), true, "/unless")
+                } else {
+                    nodes.push(Node::Var(tag.clone()));
+                    continue;
+                };
+
+                let (then, mut end) = parse(tokens, position)?;
+                let otherwise = if end.as_deref() == Some("else") {
+                    let (otherwise, after) = parse(tokens, position)?;
+                    end = after;
+                    otherwise
+                } else {
+                    Vec::new()
+                };
+                match end.as_deref() {
+                    Some(end) if end == closer => {}
+                    Some(end) if end.starts_with('/') || end == "else" => {
+                        bail!(
+                            "{{{{{}}}}} closed by {{{{{}}}}} in template; expected {{{{{}}}}}",
+                            tag,
+                            end,
+                            closer
+                        )
+                    }
+                    _ => bail!("Unclosed {{{{{}}}}} in template", tag),
+                }
+                nodes.push(Node::If {
+                    name: name.to_string(),
+                    negate,
+                    then,
+                    otherwise,
+                });
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn evaluate(nodes: &[Node], lookup: &dyn Fn(&str) -> Option<String>, output: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Var(name) => output.push_str(&lookup(name).unwrap_or_default()),
+            Node::If {
+                name,
+                negate,
+                then,
+                otherwise,
+            } => {
+                let truthy = lookup(name).is_some_and(|value| !value.is_empty());
+                if truthy != *negate {
+                    evaluate(then, lookup, output);
+                } else {
+                    evaluate(otherwise, lookup, output);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render_with(template: &str, vars: &[(&str, &str)]) -> Result<String> {
+        render(template, &|name| {
+            vars.iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        })
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let output = render_with(
+            "Hello {{ name }}, from {{place}}",
+            &[("name", "Ada"), ("place", "here")],
+        );
+        assert_eq!(output.unwrap(), "Hello Ada, from here");
+    }
+
+    #[test]
+    fn unknown_variables_render_empty() {
+        assert_eq!(render_with("[{{missing}}]", &[]).unwrap(), "[]");
+        assert_eq!(
+            render_with("{{#if missing}}yes{{else}}no{{/if}}", &[]).unwrap(),
+            "no"
+        );
+    }
+
+    #[test]
+    fn nests_conditions() {
+        let template =
+            "{{#if a}}A{{#unless b}}!b{{else}}b{{/unless}}{{else}}{{#if b}}B{{/if}}{{/if}}";
+        assert_eq!(render_with(template, &[("a", "1")]).unwrap(), "A!b");
+        assert_eq!(
+            render_with(template, &[("a", "1"), ("b", "1")]).unwrap(),
+            "Ab"
+        );
+        assert_eq!(render_with(template, &[("b", "1")]).unwrap(), "B");
+        assert_eq!(render_with(template, &[("a", "")]).unwrap(), "");
+    }
+
+    #[test]
+    fn escapes_braces() {
+        assert_eq!(
+            render_with("\\{{name}} is {{name}}", &[("name", "x")]).unwrap(),
+            "{{name}} is x"
+        );
+        assert_eq!(placeholders("\\{{skipped}} {{kept}}").unwrap(), ["kept"]);
+    }
+
+    #[test]
+    fn rejects_mismatched_tags() {
+        for template in [
+            "{{#if a}}x{{/unless}}",
+            "{{#unless a}}x{{/if}}",
+            "{{#if a}}x{{else}}y{{/unless}}",
+            "{{#if a}}x{{else}}y{{else}}z{{/if}}",
+            "x{{/if}}",
+            "{{else}}",
+        ] {
+            assert!(validate(template).is_err(), "{template}");
+        }
+    }
+
+    #[test]
+    fn rejects_unclosed_tags() {
+        for template in [
+            "{{#if a}}x",
+            "{{#if a}}x{{else}}y",
+            "{{name",
+            "{{#if a}}{{#if b}}x{{/if}}",
+        ] {
+            assert!(validate(template).is_err(), "{template}");
+        }
+    }
+}